@@ -0,0 +1,216 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::{ready, Ready};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use actix_web::dev::{Payload, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use chrono::Utc;
+
+use crate::auth::Identity;
+use crate::cache::Process;
+use crate::error::ControlApiError;
+use crate::store::{DayRange, ProcessStore, StoreError};
+
+pub use consumer_control_client::model::{AuditEntry, ProcessChange, ProcessState};
+
+/// Header naming whoever made the request when authentication is disabled.
+pub const CALLER_HEADER: &str = "X-Caller";
+/// Header carrying the request ID; one is generated when it is missing.
+pub const REQUEST_ID_HEADER: &str = "X-Request-ID";
+/// `Warning` sent with a change that was saved but is missing from the audit trail.
+pub const UNRECORDED_WARNING: &str = "199 - \"the change was saved but could not be recorded in the audit trail\"";
+
+/// Who made a request, recorded with every change it causes.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub caller: String,
+    pub request_id: String,
+    // Shared with the copy kept in the request, which `warn_if_unrecorded` reads.
+    unrecorded: Arc<AtomicBool>,
+}
+
+impl RequestContext {
+    pub fn new(caller: &str, request_id: &str) -> Self {
+        RequestContext {
+            caller: caller.to_string(),
+            request_id: request_id.to_string(),
+            unrecorded: Arc::default(),
+        }
+    }
+
+    /// Notes that a change of this request was saved without its audit entries.
+    pub fn mark_unrecorded(&self) {
+        self.unrecorded.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_unrecorded(&self) -> bool {
+        self.unrecorded.load(Ordering::Relaxed)
+    }
+}
+
+/// Adds `UNRECORDED_WARNING` to the response of a request that saved a
+/// change without its audit entries.
+pub fn warn_if_unrecorded<B>(mut res: ServiceResponse<B>) -> ServiceResponse<B> {
+    let unrecorded = res.request().extensions().get::<RequestContext>().is_some_and(RequestContext::is_unrecorded);
+    if unrecorded {
+        res.headers_mut().insert(header::WARNING, HeaderValue::from_static(UNRECORDED_WARNING));
+    }
+    res
+}
+
+impl FromRequest for RequestContext {
+    type Error = ControlApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let header = |name: &str| {
+            req.headers().get(name)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string())
+        };
+        // An authenticated identity can't be spoofed by a header, so it always wins.
+        // Fall back to the client address so an unnamed caller can still be traced.
+        let caller = req.extensions().get::<Identity>().map(|identity| identity.subject.clone())
+            .or_else(|| header(CALLER_HEADER))
+            .or_else(|| req.peer_addr().map(|addr| addr.ip().to_string()))
+            .unwrap_or_else(|| "unknown".to_string());
+        let request_id = header(REQUEST_ID_HEADER)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let ctx = RequestContext::new(&caller, &request_id);
+        req.extensions_mut().insert(ctx.clone());
+        ready(Ok(ctx))
+    }
+}
+
+/// Lists every process that differs between `previous` and `current`, by name.
+pub fn diff(previous: &HashMap<String, Process>, current: &HashMap<String, Process>) -> Vec<ProcessChange> {
+    let names: BTreeSet<&String> = previous.keys().chain(current.keys()).collect();
+    names.into_iter()
+        .filter_map(|name| {
+            let (before, after) = (previous.get(name), current.get(name));
+            let action = match (before, after) {
+                (None, Some(_)) => "added",
+                (Some(_), None) => "deleted",
+                (Some(b), Some(a)) if b != a => "updated",
+                _ => return None,
+            };
+            Some(ProcessChange {
+                process_name: name.clone(),
+                action: action.to_string(),
+                previous: before.map(ProcessState::from),
+                new: after.map(ProcessState::from),
+            })
+        })
+        .collect()
+}
+
+/// The audit entries recording every change between `previous` and `current` on behalf of `ctx`.
+pub fn diff_entries(previous: &HashMap<String, Process>, current: &HashMap<String, Process>, ctx: &RequestContext) -> Vec<AuditEntry> {
+    let timestamp = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    diff(previous, current).into_iter()
+        .map(|change| AuditEntry {
+            process_name: change.process_name,
+            action: change.action,
+            previous: change.previous,
+            new: change.new,
+            timestamp: timestamp.clone(),
+            caller: ctx.caller.clone(),
+            request_id: ctx.request_id.clone(),
+            renamed_from: None,
+            renamed_to: None,
+        })
+        .collect()
+}
+
+/// Records the deletion of `from` and the addition of `to` among `entries`
+/// as one process being renamed, under both names.
+pub fn renamed(entries: Vec<AuditEntry>, from: &str, to: &str) -> Vec<AuditEntry> {
+    let previous = entries.iter().find(|e| e.process_name == from).and_then(|e| e.previous.clone());
+    entries.into_iter()
+        .map(|mut entry| {
+            if entry.process_name == from {
+                entry.action = "renamed".to_string();
+                entry.renamed_to = Some(to.to_string());
+            } else if entry.process_name == to {
+                entry.action = "renamed".to_string();
+                entry.previous = previous.clone();
+                entry.renamed_from = Some(from.to_string());
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Every audit entry of `process_name` recorded on the days in `days`,
+/// oldest first, preceded by those of the names it had before it was renamed.
+pub async fn history(store: &dyn ProcessStore, process_name: &str, days: &DayRange) -> Result<Vec<AuditEntry>, StoreError> {
+    let mut entries = store.load_audit(process_name, days).await?;
+    let mut history = Vec::new();
+    let mut name = process_name.to_string();
+    // The renames already followed, so a chain that comes back to an earlier
+    // name (a -> b -> a) is walked once.
+    let mut followed = HashSet::new();
+    loop {
+        let rename = entries.iter().enumerate().rev().find_map(|(i, e)| Some((i, e.renamed_from.clone()?)));
+        let Some((start, from)) = rename else {
+            history.splice(0..0, entries);
+            return Ok(history);
+        };
+        let request_id = entries[start].request_id.clone();
+        // What the name recorded before this rename gave it to the process is not its own.
+        history.splice(0..0, entries.split_off(start));
+        if !followed.insert(request_id.clone()) {
+            return Ok(history);
+        }
+        // Only what the old name recorded before this rename; anything later belongs to another process.
+        let mut earlier = store.load_audit(&from, days).await?;
+        match earlier.iter().position(|e| e.request_id == request_id && e.renamed_to.as_deref() == Some(name.as_str())) {
+            Some(end) => earlier.truncate(end),
+            None => return Ok(history),
+        }
+        entries = earlier;
+        name = from;
+    }
+}
+
+pub fn to_jsonl(entries: &[AuditEntry]) -> Result<Vec<u8>, serde_json::Error> {
+    let mut contents = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut contents, entry)?;
+        contents.push(b'\n');
+    }
+    Ok(contents)
+}
+
+/// Parses a JSONL audit file, keeping the entries for `process_name`.
+pub fn from_jsonl(contents: &[u8], process_name: &str) -> Result<Vec<AuditEntry>, serde_json::Error> {
+    let mut entries = Vec::new();
+    for line in contents.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+        let entry: AuditEntry = serde_json::from_slice(line)?;
+        if entry.process_name == process_name {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[test]
+fn test_diff_entries() {
+    let p1 = crate::cache::create_process("process1", true, None);
+    let mut p1_stopped = p1.clone();
+    p1_stopped.run = false;
+    let p2 = crate::cache::create_process("process2", true, None);
+    let previous = HashMap::from([("process1".to_string(), p1), ("process2".to_string(), p2.clone())]);
+    let current = HashMap::from([("process1".to_string(), p1_stopped), ("process3".to_string(), p2)]);
+
+    let entries = diff_entries(&previous, &current, &RequestContext::new("alice", "req-1"));
+    let actions: Vec<(&str, &str)> = entries.iter().map(|e| (e.process_name.as_str(), e.action.as_str())).collect();
+    assert_eq!(actions, vec![("process1", "updated"), ("process2", "deleted"), ("process3", "added")]);
+    assert!(entries[0].previous.as_ref().unwrap().run && !entries[0].new.as_ref().unwrap().run);
+
+    let contents = to_jsonl(&entries).unwrap();
+    assert_eq!(from_jsonl(&contents, "process2").unwrap()[0].caller, "alice");
+}
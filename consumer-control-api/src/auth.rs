@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::path::Path;
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use actix_web::http::Method;
+use actix_web::{Error, HttpMessage};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::Either;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::ControlApiError;
+
+/// Header carrying a static API key.
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Seconds of clock skew tolerated when checking token expiry.
+const CLOCK_LEEWAY_SECS: u64 = 30;
+
+/// What a caller may do. Roles are ordered, so an operator can do
+/// everything a read-only caller can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    ReadOnly,
+    Operator,
+}
+
+impl Role {
+    fn name(&self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read_only",
+            Role::Operator => "operator",
+        }
+    }
+}
+
+/// An authenticated caller, stored in the request extensions by `Authentication`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub role: Role,
+}
+
+/// One way of proving who the caller is.
+pub trait Authenticator: Send + Sync {
+    /// `Ok(None)` when the request carries no credentials of this kind,
+    /// `Err` when it carries credentials that are not valid.
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Identity>, String>;
+}
+
+#[derive(Deserialize)]
+struct ApiKeyEntry {
+    name: String,
+    key: String,
+    role: Role,
+}
+
+/// Static API keys sent in `X-API-Key`, loaded from a JSON file of
+/// `{"name": ..., "key": ..., "role": "read_only" | "operator"}` entries.
+pub struct ApiKeyAuthenticator {
+    // Keyed by the SHA-256 of the key so lookups don't compare secrets directly.
+    keys: HashMap<[u8; 32], Identity>,
+}
+
+impl ApiKeyAuthenticator {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read(path).map_err(|e| format!("Failed to read API keys file {}: {}", path.display(), e))?;
+        let entries: Vec<ApiKeyEntry> = serde_json::from_slice(&contents)
+            .map_err(|e| format!("Invalid API keys file {}: {}", path.display(), e))?;
+        Ok(Self::new(entries.into_iter().map(|e| (e.key, Identity { subject: e.name, role: e.role }))))
+    }
+
+    pub fn new(keys: impl IntoIterator<Item = (String, Identity)>) -> Self {
+        ApiKeyAuthenticator {
+            keys: keys.into_iter().map(|(key, identity)| (Sha256::digest(key.as_bytes()).into(), identity)).collect(),
+        }
+    }
+}
+
+impl Authenticator for ApiKeyAuthenticator {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Identity>, String> {
+        let key = match headers.get(API_KEY_HEADER) {
+            Some(key) => key.as_bytes(),
+            None => return Ok(None),
+        };
+        let digest: [u8; 32] = Sha256::digest(key).into();
+        self.keys.get(&digest).cloned().map(Some).ok_or_else(|| "Invalid API key".to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    k: String,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    sub: String,
+    role: Role,
+    exp: u64,
+    nbf: Option<u64>,
+}
+
+/// HS256 bearer tokens verified against a local JWK set of symmetric
+/// (`"kty": "oct"`) keys. Tokens must carry `sub`, `role` and `exp` claims.
+pub struct JwtAuthenticator {
+    keys: Vec<(Option<String>, Vec<u8>)>,
+}
+
+impl JwtAuthenticator {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read(path).map_err(|e| format!("Failed to read JWT key set {}: {}", path.display(), e))?;
+        let set: JwkSet = serde_json::from_slice(&contents)
+            .map_err(|e| format!("Invalid JWT key set {}: {}", path.display(), e))?;
+        let mut keys = Vec::new();
+        for jwk in set.keys {
+            if jwk.kty != "oct" {
+                return Err(format!("Unsupported key type '{}' in {}, only 'oct' keys are supported", jwk.kty, path.display()));
+            }
+            let secret = URL_SAFE_NO_PAD.decode(jwk.k.trim_end_matches('='))
+                .map_err(|e| format!("Invalid key in {}: {}", path.display(), e))?;
+            keys.push((jwk.kid, secret));
+        }
+        Ok(JwtAuthenticator::new(keys))
+    }
+
+    pub fn new(keys: Vec<(Option<String>, Vec<u8>)>) -> Self {
+        JwtAuthenticator { keys }
+    }
+
+    fn verify(&self, token: &str) -> Result<Identity, String> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s), None) => (h, p, s),
+            _ => return Err("Malformed bearer token".to_string()),
+        };
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| "Malformed bearer token".to_string());
+        let jwt_header: JwtHeader = serde_json::from_slice(&decode(header)?)
+            .map_err(|_| "Malformed bearer token".to_string())?;
+        if jwt_header.alg != "HS256" {
+            return Err(format!("Unsupported token algorithm '{}'", jwt_header.alg));
+        }
+        let signature = decode(signature)?;
+        let signed = &token[..header.len() + 1 + payload.len()];
+        let verified = self.keys.iter()
+            .filter(|(kid, _)| jwt_header.kid.is_none() || kid == &jwt_header.kid)
+            .any(|(_, secret)| {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+                mac.update(signed.as_bytes());
+                mac.verify_slice(&signature).is_ok()
+            });
+        if !verified {
+            return Err("Invalid token signature".to_string());
+        }
+        let claims: JwtClaims = serde_json::from_slice(&decode(payload)?)
+            .map_err(|e| format!("Invalid token claims: {}", e))?;
+        let now = crate::cache::get_current_time();
+        if claims.exp + CLOCK_LEEWAY_SECS < now {
+            return Err("Token has expired".to_string());
+        }
+        if claims.nbf.is_some_and(|nbf| nbf > now + CLOCK_LEEWAY_SECS) {
+            return Err("Token is not valid yet".to_string());
+        }
+        Ok(Identity { subject: claims.sub, role: claims.role })
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Identity>, String> {
+        let token = match headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+            Some(value) => match value.strip_prefix("Bearer ") {
+                Some(token) => token.trim(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        self.verify(token).map(Some)
+    }
+}
+
+/// Decides which role each request needs and whether its caller has it.
+pub struct Authorizer {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    allow_anonymous_reads: bool,
+}
+
+impl Authorizer {
+    pub fn new(authenticators: Vec<Box<dyn Authenticator>>, allow_anonymous_reads: bool) -> Self {
+        Authorizer { authenticators, allow_anonymous_reads }
+    }
+
+    /// Builds the authorizer from the configured key files. With neither
+    /// file configured every request is let through, as before authentication existed.
+    pub fn from_files(api_keys_file: Option<&Path>, jwt_keys_file: Option<&Path>, allow_anonymous_reads: bool) -> Result<Self, String> {
+        let mut authenticators: Vec<Box<dyn Authenticator>> = Vec::new();
+        if let Some(path) = api_keys_file {
+            authenticators.push(Box::new(ApiKeyAuthenticator::from_file(path)?));
+            info!("Accepting API keys from {}", path.display());
+        }
+        if let Some(path) = jwt_keys_file {
+            authenticators.push(Box::new(JwtAuthenticator::from_file(path)?));
+            info!("Accepting bearer tokens signed with keys from {}", path.display());
+        }
+        if authenticators.is_empty() {
+            warn!("No API keys or JWT keys configured, authentication is disabled");
+        }
+        Ok(Authorizer::new(authenticators, allow_anonymous_reads))
+    }
+
+    fn required_role(req: &ServiceRequest) -> Option<Role> {
+        let path = req.path();
+        // Docs and the probes used by load balancers and Prometheus stay open.
+        if path == "/" || path.starts_with("/docs") || matches!(path, "/healthz" | "/readyz" | "/metrics") {
+            return None;
+        }
+        match *req.method() {
+            Method::GET | Method::HEAD => Some(Role::ReadOnly),
+            _ => Some(Role::Operator),
+        }
+    }
+
+    pub fn authorize(&self, req: &ServiceRequest) -> Result<Option<Identity>, ControlApiError> {
+        // Open paths ignore credentials, so a probe with a stale key still gets through.
+        let Some(required) = Self::required_role(req) else { return Ok(None) };
+        if self.authenticators.is_empty() {
+            return Ok(None);
+        }
+        let mut identity = None;
+        for authenticator in &self.authenticators {
+            if let Some(found) = authenticator.authenticate(req.headers()).map_err(ControlApiError::Unauthorized)? {
+                identity = Some(found);
+                break;
+            }
+        }
+        match identity {
+            None if required == Role::ReadOnly && self.allow_anonymous_reads => Ok(None),
+            None => Err(ControlApiError::Unauthorized("Missing credentials".to_string())),
+            Some(identity) if identity.role >= required => Ok(Some(identity)),
+            Some(identity) => Err(ControlApiError::Forbidden(format!(
+                "{} {} requires the {} role, {} has {}",
+                req.method(), req.path(), required.name(), identity.subject, identity.role.name()
+            ))),
+        }
+    }
+}
+
+/// Middleware rejecting requests whose caller lacks the role the route needs.
+pub struct Authentication {
+    authorizer: Arc<Authorizer>,
+}
+
+impl Authentication {
+    pub fn new(authorizer: Arc<Authorizer>) -> Self {
+        Authentication { authorizer }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service,
+            authorizer: self.authorizer.clone(),
+        }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+    authorizer: Arc<Authorizer>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match self.authorizer.authorize(&req) {
+            Ok(identity) => {
+                if let Some(identity) = identity {
+                    req.extensions_mut().insert(identity);
+                }
+                Either::Left(self.service.call(req))
+            },
+            Err(e) => {
+                warn!("Rejected {} {}: {}", req.method(), req.path(), e);
+                Either::Right(ready(Err(e.into())))
+            },
+        }
+    }
+}
+
+#[test]
+fn test_authorize_roles() {
+    use actix_web::test::TestRequest;
+
+    let secret = b"not-a-real-secret".to_vec();
+    let sign = |claims: &str| {
+        let signed = format!("{}.{}", URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#), URL_SAFE_NO_PAD.encode(claims));
+        let mut mac = Hmac::<Sha256>::new_from_slice(&secret).unwrap();
+        mac.update(signed.as_bytes());
+        format!("Bearer {}.{}", signed, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    };
+    let reader = ApiKeyAuthenticator::new([("reader-key".to_string(), Identity { subject: "consumer".to_string(), role: Role::ReadOnly })]);
+    let authorizer = Authorizer::new(vec![Box::new(reader), Box::new(JwtAuthenticator::new(vec![(None, secret.clone())]))], false);
+    let exp = crate::cache::get_current_time() + 600;
+
+    let get = TestRequest::get().uri("/process?process_name=p1").insert_header((API_KEY_HEADER, "reader-key")).to_srv_request();
+    assert_eq!(authorizer.authorize(&get).unwrap().unwrap().subject, "consumer");
+    let stop = TestRequest::patch().uri("/processes/stop").insert_header((API_KEY_HEADER, "reader-key")).to_srv_request();
+    assert!(matches!(authorizer.authorize(&stop), Err(ControlApiError::Forbidden(_))));
+    let anonymous = TestRequest::get().uri("/processes").to_srv_request();
+    assert!(matches!(authorizer.authorize(&anonymous), Err(ControlApiError::Unauthorized(_))));
+    let probe = TestRequest::get().uri("/healthz").insert_header((AUTHORIZATION, "Bearer not-a-token")).to_srv_request();
+    assert!(authorizer.authorize(&probe).unwrap().is_none());
+
+    let operator = sign(&format!(r#"{{"sub":"alice","role":"operator","exp":{}}}"#, exp));
+    let stop = TestRequest::patch().uri("/processes/stop").insert_header((AUTHORIZATION, operator.clone())).to_srv_request();
+    assert_eq!(authorizer.authorize(&stop).unwrap().unwrap().role, Role::Operator);
+    let tampered = format!("{}x", operator);
+    let stop = TestRequest::patch().uri("/processes/stop").insert_header((AUTHORIZATION, tampered)).to_srv_request();
+    assert!(matches!(authorizer.authorize(&stop), Err(ControlApiError::Unauthorized(_))));
+    let expired = sign(r#"{"sub":"alice","role":"operator","exp":1}"#);
+    let stop = TestRequest::patch().uri("/processes/stop").insert_header((AUTHORIZATION, expired)).to_srv_request();
+    assert!(matches!(authorizer.authorize(&stop), Err(ControlApiError::Unauthorized(_))));
+}
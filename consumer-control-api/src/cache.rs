@@ -1,509 +1,1003 @@
 use std::collections::HashMap;
-use std::hash::Hash;
-use aws_sdk_s3::operation::put_object;
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use std::fs::{soft_link, File};
-use std::io::{BufReader};
-use serde::{Deserialize, Serialize};
-use serde_json::{from_reader};
-use anyhow::Result;
-
-use aws_sdk_s3::Client;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use once_cell::sync::OnceCell;
-use std::env;
-use std::path::PathBuf;
-use tokio::runtime::Runtime;
-use tokio::task::spawn_blocking;
-use log::{debug, error, log_enabled, info, Level};
-use chrono::Local;
-use chrono::format::strftime::StrftimeItems;
-use glob::Pattern;
-use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{ProcessQueryParams, ProcessQuery, ProcessPatchInput, ProcessMessage};
+use arc_swap::ArcSwap;
+use tokio::sync::{watch, Mutex};
 
-pub static S3_CLIENT: OnceCell<Arc<Client>> = OnceCell::new();
+use log::{error, info};
+use chrono::{DateTime, Local, Utc};
+use std::error::Error;
 
-pub fn get_client_new() -> Arc<Client> {
-    S3_CLIENT.get().expect("S3_CLIENT not initialized").clone()
+use crate::audit::{self, AuditEntry, ProcessChange, RequestContext};
+use crate::error::ControlApiError;
+use crate::groups;
+use crate::tags;
+use crate::metrics::METRICS;
+use crate::store::{LastKnownGood, ProcessStore, StoreError};
+use crate::query::Filter;
+use crate::{ProcessPatchInput, ProcessMessage};
+
+pub use consumer_control_client::model::{create_process, apply_dependencies, Process};
+
+/// The processes as last loaded or saved by `MyCache`, readable without
+/// taking the cache lock. Writers publish a whole new map, so readers never
+/// see a half-applied change.
+pub struct ProcessView {
+    current: ArcSwap<HashMap<String, Process>>,
+    etag: ArcSwap<String>,
+    // Seconds since the epoch when the map was last confirmed against the store.
+    refreshed_at: AtomicU64,
+    // Reads fail once the map is older than this; 0 disables the limit.
+    max_staleness_secs: u64,
+    // Set while serving a last-known-good snapshot the store has not confirmed.
+    degraded: AtomicBool,
+    // Bumped whenever the map changes, to wake up watchers.
+    changes: watch::Sender<u64>,
 }
 
-fn parse_s3_filename(filename: &str) -> Option<(String, String, String)> {
-    let s3_prefix = "s3://";
-    if !filename.starts_with(s3_prefix) {
-        return None;
+impl ProcessView {
+    pub(crate) fn new(processes: HashMap<String, Process>, etag: &str, max_staleness_secs: u64) -> Self {
+        ProcessView {
+            current: ArcSwap::from_pointee(processes),
+            etag: ArcSwap::from_pointee(etag.to_string()),
+            refreshed_at: AtomicU64::new(get_current_time()),
+            max_staleness_secs,
+            degraded: AtomicBool::new(false),
+            changes: watch::channel(0).0,
+        }
     }
 
-    let without_prefix = &filename[s3_prefix.len()..];
-    let parts: Vec<&str> = without_prefix.splitn(2, '/').collect();
-    if parts.len() != 2 {
-        return None;
+    fn publish(&self, processes: HashMap<String, Process>, etag: &str) {
+        self.current.store(Arc::new(processes));
+        self.etag.store(Arc::new(etag.to_string()));
+        self.mark_fresh();
+        self.changes.send_modify(|v| *v += 1);
     }
 
-    let bucket = parts[0].to_string();
-    let key = parts[1].to_string();
+    fn mark_fresh(&self) {
+        self.refreshed_at.store(get_current_time(), Ordering::Relaxed);
+        if self.degraded.swap(false, Ordering::Relaxed) {
+            info!("Store reachable again, leaving degraded mode");
+        }
+    }
 
-    let key_parts: Vec<&str> = key.rsplitn(2, '/').collect();
-    let file_name = key_parts[0].to_string();
+    fn mark_degraded(&self, saved_at: u64) {
+        self.refreshed_at.store(saved_at, Ordering::Relaxed);
+        self.degraded.store(true, Ordering::Relaxed);
+    }
 
-    Some((bucket, key, file_name))
-}
+    /// True while serving a last-known-good snapshot because the store could not be read.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
 
-async fn get_etag(s3_file_name: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let client = get_client_new();
-    match parse_s3_filename(&s3_file_name) {
-        Some((bucket, key, _)) => {
-            let head_object_output = client.head_object().bucket(&bucket).key(&key).send().await?;
-            match head_object_output.e_tag {
-                Some(etag) => Ok(etag),
-                None => Err("Failed to get ETag from HeadObjectOutput".into())
-            }
-        },
-        None => Err("Invalid S3 filename".into())
-    }
-}
-
-async fn dowload_file_from_s3_to_temp_dir_sdk(s3_file_name: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let client = get_client_new();
-    match parse_s3_filename(&s3_file_name) {
-        Some((bucket, key, file_name)) => {
-            let temp_dir = env::var("tmpdir").expect("tmpdir not set");
-            let mut load_path = PathBuf::from(temp_dir);
-            load_path.push(file_name);
-            let download_file_name = load_path.to_str().ok_or_else(|| "Invalid file path".to_string())?;
-            let get_object_output = client.get_object().bucket(&bucket).key(&key).send().await?;
-            let mut stream = get_object_output.body.into_async_read();
-            let mut file = tokio::fs::File::create(download_file_name).await?;
-            tokio::io::copy(&mut stream, &mut file).await?;
-            Ok(get_object_output.e_tag.unwrap())
-        },
-        None => Err("Invalid S3 filename".into())
-    }
-}
-
-async fn upload_file_to_s3_(s3_file_name: &str, file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let client = get_client_new();
-    match parse_s3_filename(&s3_file_name) {
-        Some((bucket, key, _)) => {
-            match upload_object(&client, &bucket, file_path, &key).await {
-                Ok(output) => Ok(output),
-                Err(e) => Err(format!("Error uploading file: {:?}", e).into())
-            }
-        },
-        None => Err("Invalid S3 filename".into())
+    pub fn age_secs(&self) -> u64 {
+        get_current_time().saturating_sub(self.refreshed_at.load(Ordering::Relaxed))
+    }
+
+    /// Version of the stored document the map was loaded from or saved as.
+    pub fn etag(&self) -> String {
+        self.etag.load().to_string()
+    }
+
+    /// Numbers of running and stopped processes at `now`, however stale the map is.
+    pub fn run_counts(&self, now: DateTime<Utc>) -> (usize, usize) {
+        let processes = self.current.load();
+        let running = processes.values().filter(|p| p.resolved(now).run).count();
+        (running, processes.len() - running)
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Process {
-  pub name: String,
-  pub run: bool,
-  pub tags: Option<Vec<String>>,
-  pub effective: String, // Store effective date/time as a string for simplicity
+    /// The current map, or an error when it has not been refreshed within the
+    /// staleness limit. A last-known-good snapshot is served however old it is,
+    /// since falling back to it was deliberate.
+    pub fn load(&self) -> Result<Arc<HashMap<String, Process>>, ControlApiError> {
+        let age = self.age_secs();
+        if self.max_staleness_secs > 0 && age > self.max_staleness_secs && !self.is_degraded() {
+            return Err(ControlApiError::StorageUnavailable(StoreError::Backend(format!(
+                "process data is stale, last refreshed {} seconds ago", age
+            ))));
+        }
+        Ok(self.current.load_full())
+    }
+
+    /// Notifies the receiver whenever the processes change.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    pub fn get_process(&self, process_name: &str) -> Result<Option<Process>, ControlApiError> {
+        Ok(self.load()?.get(process_name).cloned())
+    }
+
+    /// The processes matching `filter`, with `run` resolved for `now`.
+    pub fn filter_processes(&self, filter: &Filter, now: DateTime<Utc>) -> Result<Vec<Process>, ControlApiError> {
+        let processes = self.load()?.values().map(|p| p.resolved(now)).collect();
+        Ok(filter_processes(processes, filter))
+    }
 }
 
 pub struct MyCache {
     pub all_processes: HashMap<String, Process>,
-    pub cache_time: u64,
     pub etag: String,
+    pub store: Arc<dyn ProcessStore>,
+    view: Arc<ProcessView>,
+    snapshot: Option<LastKnownGood>,
 }
 
-pub fn create_process(name: &str, run: bool, tags: Option<Vec<String>>) -> Process {
-    let now = Local::now();
-    let effective = now.format("%Y-%m-%d %H:%M:%S").to_string();
-    Process {
-        name: name.to_string(),
-        run,
-        tags,
-        effective,
+pub fn update_process_partial(process: &mut Process, input: &ProcessPatchInput) {
+    if let Some(t) = &input.tags {
+        process.tags = Some(t.clone()); // Update tags only if Some(tags) is provided
     }
-}
-
-pub fn update_process_partial(process: &mut Process, run: Option<bool>, tags: Option<Vec<String>>) {
-    if let Some(t) = tags {
-        process.tags = Some(t); // Update tags only if Some(tags) is provided
+    if let Some(s) = &input.schedule {
+        // An empty schedule removes the existing one
+        process.schedule = if s.is_empty() { None } else { Some(s.clone()) };
     }
-    if let Some(r) = run {
+    apply_dependencies(process, &input.group, &input.depends_on);
+    process.metadata.apply(&input.metadata);
+    let now = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    if let Some(r) = input.run {
         process.run = r;
+        // A reason only explains the change it came with.
+        process.reason = input.reason.clone();
+        // Only an explicit run change wins over transitions already due.
+        process.run_set_at = Some(now.clone());
     }
-    let now = Local::now();
-    process.effective = now.format("%Y-%m-%d %H:%M:%S").to_string();
+    process.effective = now;
 }
 
-fn update_process(process: &mut Process, run: bool, tags: Option<Vec<String>>) {
-    process.run = run;
-    if let Some(t) = tags {
-        process.tags = Some(t); // Update tags only if Some(tags) is provided
-    }
-    let now = chrono::Local::now();
-    process.effective = now.format("%Y-%m-%d %H:%M:%S").to_string();
+fn to_list(processes: &HashMap<String, Process>) -> Vec<Process> {
+    processes.values().cloned().collect()
 }
 
-fn to_map(processes: Vec<Process>) -> HashMap<String, Process> {
-    let mut map = HashMap::new();
-    for p in processes {
-        map.insert(p.name.clone(), p);
-    }
-    map
-}
+/// Number of times a mutation is attempted before a write conflict is reported.
+const MAX_WRITE_ATTEMPTS: u32 = 5;
 
-fn to_list(processes: &HashMap<String, Process>) -> Vec<Process> {
-    let mut list = Vec::new();
-    for (_, v) in processes {
-        list.push(v.clone());
-    }
-    list
-}
-
-pub async fn read_data() -> Result<(HashMap<String, Process>, String), Box<dyn std::error::Error>> {
-    let s3_file_name = env::var("s3_file").expect("s3_file not set");
-    let temp_dir = env::var("tmpdir").expect("tmpdir not set");
-    let mut load_path = PathBuf::from(temp_dir);
-    match parse_s3_filename(&s3_file_name) {
-        Some((_, _, file_name)) => {
-            load_path.push(file_name);
-            match dowload_file_from_s3_to_temp_dir_sdk(s3_file_name.as_str()).await {
-                Ok(etag) => {
-                    info!("Downloaded s3 file with ETag: {}", etag);
-                    let file = File::open(load_path)?;
-                    let reader = BufReader::new(file);
-                  
-                    // Read the JSON data from the file
-                    let data: Vec<Process> = from_reader(reader)?;
-                    let data_map = to_map(data);
-                    Ok((data_map, etag))
-                },
-                Err(e) => {
-                    Err(format!("Error downloading file: {:?}", e).into())
-                }
-            }
-        },
-        None => Err("Invalid S3 filename".into())
-    }
+/// Exponential backoff from 50ms with up to 50% jitter, so replicas that
+/// collided once don't retry in lockstep.
+fn write_backoff(attempt: u32) -> Duration {
+    let base_ms = 50u64 << (attempt - 1);
+    let jitter_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos() as u64 % (base_ms / 2 + 1);
+    Duration::from_millis(base_ms + jitter_ms)
 }
 
 pub fn get_current_time() -> u64 {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
 }
 
-async fn update_data(data: HashMap<String, Process>, etag: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let s3_file_name = env::var("s3_file").expect("s3_file not set");
-    let temp_dir = env::var("tmpdir").expect("tmpdir not set");
-    let mut load_path = PathBuf::from(temp_dir);
-    match parse_s3_filename(&s3_file_name) {
-        Some((_, _, file_name)) => {
-            load_path.push(file_name);
-            let upload_file_name = load_path.to_str().ok_or_else(|| "Invalid file path".to_string())?;
-            let file = File::create(&upload_file_name)?;
-            let data_list = to_list(&data);
-            serde_json::to_writer(file, &data_list)?;
-            let etag_new = get_etag(s3_file_name.as_str()).await?;
-            if etag != etag_new {
-                return Err("Please retry the operation".into());
-            }
-            match upload_file_to_s3_(&s3_file_name, upload_file_name).await {
-                Ok(etag_c) => Ok(etag_c),
-                Err(e) => Err(format!("Error uploading file: {:?}", e).into())
-            }
-        },
-        None => return Err("Invalid S3 filename".into())
-    }
-}
-
-async fn init_cache() -> MyCache {
-    match read_data().await {
-        Ok((data, etag)) => MyCache {
-            all_processes: data,
-            cache_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-            etag,
-        },
-        Err(e) => {
-            error!("Error reading cache: {:?}", e);
-            std::process::exit(1);
-        }
-    }
-}
-
-fn filter_processes_by_tags(processes: Vec<Process>, input_tags: &Option<Vec<String>>) -> Vec<Process> {
-    // print input_tags
-    match input_tags {
-        Some(tags) => processes.into_iter()
-            .filter(|process| {
-                // If process.tags is None, it cannot contain any tags, so exclude it by returning false
-                if let Some(process_tags) = &process.tags {
-                    // Check if all input tags are contained within the process's tags
-                    tags.iter().all(|tag| process_tags.contains(tag))
-                } else {
-                    false
-                }
-            })
-            .collect(),
-        None => processes,
-    }
+fn filter_processes(processes: Vec<Process>, filter: &Filter) -> Vec<Process> {
+    processes.into_iter().filter(|p| filter.matches(p)).collect()
 }
 
-fn filter_processes_by_name_prefix(processes: Vec<Process>, name_prefixes: &Option<Vec<String>>) -> Vec<Process> {
-    match name_prefixes {
-        Some(prefixes) => processes.into_iter()
-            .filter(|process| {
-                // Check if process's name starts with any of the prefixes
-                prefixes.iter().any(|prefix| process.name.starts_with(prefix))
-            })
-            .collect(),
-        None => processes,
+pub fn run_str_to_bool(input: &str) -> Result<bool, String> {
+    match input {
+        "start" => Ok(true),
+        "stop" => Ok(false),
+        _ => Err(format!("Invalid input: '{}'. Expected 'start' or 'stop'.", input)),
     }
 }
 
-fn filter_processes_by_name_patterns(processes: Vec<Process>, name_patterns: &Option<Vec<String>>) -> Result<Vec<Process>, Box<dyn Error>> {
-    match name_patterns {
-        Some(in_patterns) => {
-            let mut filtered_processes = Vec::new();
-
-            // Convert strings to glob patterns
-            // let patterns: Result<Vec<Pattern>, glob::PatternError> = name_patterns.into_iter().map(Pattern::new).collect();
-            let mut patterns = Vec::new();
-            for name_pattern in in_patterns {
-                // Use &name_pattern to pass a &str instead of String
-                match Pattern::new(&name_pattern) {
-                    Ok(pattern) => patterns.push(pattern),
-                    Err(e) => return Err(Box::new(e)),
-                }
-            }
-        
-            for process in processes {
-                for pattern in &patterns {
-                    if pattern.matches(&process.name) {
-                        filtered_processes.push(process.clone());
-                        break; // Move to the next process once a match is found
-                    }
-                }
+/// Updates the processes named in `process_inputs` and adds the missing ones,
+/// which start stopped unless `run` is given.
+fn merge_processes(all_processes: &mut HashMap<String, Process>, process_inputs: &[ProcessPatchInput]) -> Vec<ProcessMessage> {
+    let mut process_messages: Vec<ProcessMessage> = Vec::new();
+    for process_input in process_inputs {
+        match all_processes.get_mut(&process_input.name) {
+            Some(p) => {
+                update_process_partial(p, process_input);
+                process_messages.push(ProcessMessage {
+                    name: process_input.name.clone(),
+                    action: "Updated".to_string(),
+                });
+            },
+            None => {
+                let n_run = process_input.run.unwrap_or_default();
+                let mut p = create_process(&process_input.name, n_run, process_input.tags.clone());
+                p.schedule = process_input.schedule.clone().filter(|s| !s.is_empty());
+                apply_dependencies(&mut p, &process_input.group, &process_input.depends_on);
+                p.metadata.apply(&process_input.metadata);
+                p.reason = process_input.reason.clone();
+                all_processes.insert(p.name.clone(), p);
+                process_messages.push(ProcessMessage {
+                    name: process_input.name.clone(),
+                    action: "Added".to_string(),
+                });
             }
-            Ok(filtered_processes)
-        },
-        None => Ok(processes),
+        }
     }
+    process_messages
 }
 
-fn filter_processes_by_run(processes: Vec<Process>, run: &Option<bool>) -> Vec<Process> {
-    match run {
-        Some(r) => processes.into_iter()
-            .filter(|process| process.run == *r)
-            .collect(),
-        None => processes,
+/// Sets `run` on every process matching `filter` for `reason`, returning them.
+fn control_processes(all_processes: &mut HashMap<String, Process>, filter: &Filter, run: bool, reason: &Option<String>) -> Vec<Process> {
+    let processes = filter_processes(to_list(all_processes), filter);
+    let mut updated_processes: Vec<Process> = Vec::new();
+    for process in processes {
+        let p = all_processes.get_mut(&process.name).unwrap();
+        update_process_partial(p, &ProcessPatchInput { name: process.name, run: Some(run), reason: reason.clone(), ..Default::default() });
+        updated_processes.push(p.clone());
     }
+    updated_processes
 }
 
-fn filter_processes(processes: Vec<Process>, query: &ProcessQueryParams) -> Vec<Process> {
-    let mut filtered_processes = filter_processes_by_tags(processes, &query.tags);
-    filtered_processes = filter_processes_by_name_patterns(filtered_processes, &query.name_patterns).unwrap();
-    // filtered_processes = filter_processes_by_name_prefix(filtered_processes, &query.name_prefixes);
-    filtered_processes = filter_processes_by_run(filtered_processes, &query.run);
-    filtered_processes
+/// The names of the processes matching `filter`, sorted.
+fn matching_names(all_processes: &HashMap<String, Process>, filter: &Filter) -> Vec<String> {
+    let mut names: Vec<String> = all_processes.values().filter(|p| filter.matches(p)).map(|p| p.name.clone()).collect();
+    names.sort();
+    names
 }
 
-fn filter_processes_pattern(processes: Vec<Process>, query: &ProcessQuery) -> Vec<Process> {
-    let mut filtered_processes = filter_processes_by_tags(processes, &query.tags);
-    filtered_processes = filter_processes_by_name_patterns(filtered_processes, &query.name_patterns).unwrap();
-    filtered_processes
+/// Removes every process matching `filter`, returning them by name.
+fn delete_processes(all_processes: &mut HashMap<String, Process>, filter: &Filter) -> Vec<Process> {
+    let mut deleted = filter_processes(to_list(all_processes), filter);
+    for process in &deleted {
+        all_processes.remove(&process.name);
+    }
+    deleted.sort_by_key(|p| p.name.clone());
+    deleted
 }
 
-pub fn run_str_to_bool(input: &str) -> Result<bool, String> {
-    match input {
-        "start" => Ok(true),
-        "stop" => Ok(false),
-        _ => Err(format!("Invalid input: '{}'. Expected 'start' or 'stop'.", input)),
+impl MyCache {
+    /// Loads the processes from `store`. When the store can't be read and a
+    /// `snapshot` was saved by an earlier run, starts from it in degraded mode.
+    pub async fn new(store: Arc<dyn ProcessStore>, max_staleness_secs: u64, snapshot: Option<LastKnownGood>) -> Result<MyCache, Box<dyn Error>> {
+        let (all_processes, etag, saved_at) = match (store.load().await, &snapshot) {
+            (Ok((all_processes, etag)), _) => (all_processes, etag, None),
+            (Err(e), Some(snapshot)) => {
+                error!("Error reading processes from {}: {:?}", store.describe(), e);
+                let (all_processes, etag, saved_at) = snapshot.load().await
+                    .map_err(|snapshot_err| format!("{}; no usable snapshot at {}: {}", e, snapshot.describe(), snapshot_err))?;
+                error!("Starting degraded from the snapshot saved at {} by {}", saved_at, snapshot.describe());
+                (all_processes, etag, Some(saved_at))
+            },
+            (Err(e), None) => return Err(e.into()),
+        };
+        let view = Arc::new(ProcessView::new(all_processes.clone(), &etag, max_staleness_secs));
+        let cache = MyCache {
+            all_processes,
+            etag,
+            store,
+            view,
+            snapshot,
+        };
+        match saved_at {
+            Some(saved_at) => cache.view.mark_degraded(saved_at),
+            None => cache.save_snapshot().await,
+        }
+        Ok(cache)
     }
-}
 
-use tokio::task::block_in_place;
+    /// Makes the current processes visible to readers and the snapshot.
+    async fn publish(&self) {
+        self.view.publish(self.all_processes.clone(), &self.etag);
+        self.save_snapshot().await;
+    }
 
-use crate::s3_util::upload_object;
+    async fn save_snapshot(&self) {
+        if let Some(snapshot) = &self.snapshot {
+            // Only matters for the next start, so failing to save must not fail the request.
+            if let Err(e) = snapshot.save(&self.all_processes, &self.etag).await {
+                error!("Error saving snapshot to {}: {:?}", snapshot.describe(), e);
+            }
+        }
+    }
 
-impl MyCache {
-    pub async fn get_instance() -> &'static Lazy<Mutex<MyCache>> {
-        static INSTANCE: Lazy<Mutex<MyCache>> = Lazy::new(|| {
-            let cache = block_in_place(|| tokio::runtime::Runtime::new().unwrap().block_on(init_cache()));
-            Mutex::new(cache)
-        });
-        &INSTANCE
+    /// The lock-free read side of this cache.
+    pub fn view(&self) -> Arc<ProcessView> {
+        self.view.clone()
     }
 
-    pub async fn write_cache(&mut self) {
-        match update_data(self.all_processes.clone(), &self.etag).await {
+    pub async fn write_cache(&mut self) -> Result<(), StoreError> {
+        match self.store.save(&self.all_processes, &self.etag).await {
             Ok(etag) => {
                 info!("Cache written!, new etag = {}", etag);
                 self.etag = etag;
-            },
-            Err(e) => error!("Error writing cache: {:?}", e)
-        }
-    }
-
-    pub async fn should_refresh_cache(&self) -> bool {
-        let time_to_check =  get_current_time()  - self.cache_time > 60;
-        if ! time_to_check {
-            return false;
-        }
-        let s3_file_name = env::var("s3_file").expect("s3_file not set");
-        match get_etag(&s3_file_name).await {
-            Ok(etag_new) => {
-                return etag_new != self.etag;
+                self.publish().await;
+                Ok(())
             },
             Err(e) => {
-                error!("Error getting ETag: {:?}", e);
-                return true; // Decide to refresh cache if we can't get the ETag
+                error!("Error writing cache: {:?}", e);
+                Err(e)
             }
         }
     }
 
-    pub async fn refresh_cache(&mut self, force_refresh: bool) {
-        if force_refresh || self.should_refresh_cache().await {
-            let (processes, etag) = match read_data().await {
-                Ok((v, etag)) => (v, etag),
+    /// Re-reads the stored processes, applies `op` and writes the result back.
+    ///
+    /// If another writer saved in between, the latest document is re-read and
+    /// `op` re-applied, backing off between attempts, up to `MAX_WRITE_ATTEMPTS`.
+    /// `op` must leave the map untouched when it returns an error. Changes
+    /// that introduce a dependency cycle are rejected.
+    ///
+    /// Once saved, every process the write changed is appended to the audit
+    /// trail on behalf of `ctx`.
+    async fn mutate<T, F>(&mut self, ctx: &RequestContext, op: F) -> Result<T, ControlApiError>
+    where
+        F: FnMut(&mut HashMap<String, Process>) -> Result<T, ControlApiError>,
+    {
+        self.mutate_audited(ctx, op, |entries| entries).await
+    }
+
+    /// `mutate`, with `describe` rewriting the audit entries of the saved
+    /// change before they are appended.
+    async fn mutate_audited<T, F, D>(&mut self, ctx: &RequestContext, mut op: F, describe: D) -> Result<T, ControlApiError>
+    where
+        F: FnMut(&mut HashMap<String, Process>) -> Result<T, ControlApiError>,
+        D: Fn(Vec<AuditEntry>) -> Vec<AuditEntry>,
+    {
+        let mut attempt = 1;
+        loop {
+            if let Err(e) = self.refresh_cache(true).await {
+                error!("Error reading cache: {:?}", e);
+                if self.view.is_degraded() {
+                    // The snapshot may be behind the store, so changes based on it could undo newer ones.
+                    return Err(ControlApiError::StorageUnavailable(StoreError::Backend(format!(
+                        "serving the last-known-good snapshot until the store can be read again: {}", e
+                    ))));
+                }
+            }
+            let previous = self.all_processes.clone();
+            let result = op(&mut self.all_processes)?;
+            if let Err(cycle) = groups::check_cycles(&self.all_processes) {
+                // Only block changes that caused the cycle, not every later write.
+                if groups::check_cycles(&previous).is_ok() {
+                    self.all_processes = previous;
+                    return Err(ControlApiError::Validation(cycle));
+                }
+            }
+            match self.write_cache().await {
+                Ok(()) => {
+                    let entries = describe(audit::diff_entries(&previous, &self.all_processes, ctx));
+                    // The change is already saved, so a lost audit entry must not fail the
+                    // request; it is counted and the response carries a warning instead.
+                    if let Err(e) = self.store.append_audit(&entries).await {
+                        error!("Error writing audit entries for request {}: {:?}", ctx.request_id, e);
+                        METRICS.audit_failed();
+                        ctx.mark_unrecorded();
+                    }
+                    return Ok(result);
+                },
                 Err(e) => {
-                    error!("Error reading cache: {:?}", e);
-                    return; // Instead of exiting, we return to allow further handling.
+                    // Nothing was saved, so the cache must not keep the change either.
+                    self.all_processes = previous;
+                    if let StoreError::Conflict(_) = e {
+                        METRICS.write_conflict();
+                    }
+                    match e {
+                        StoreError::Conflict(etag) if attempt < MAX_WRITE_ATTEMPTS => {
+                            info!("Write conflict on attempt {}, processes were changed since etag {}", attempt, etag);
+                            tokio::time::sleep(write_backoff(attempt)).await;
+                            attempt += 1;
+                        },
+                        StoreError::Conflict(_) => {
+                            // Pick up the document that won so callers can report it.
+                            if let Err(e) = self.refresh_cache(true).await {
+                                error!("Error reading cache: {:?}", e);
+                            }
+                            return Err(e.into());
+                        },
+                        _ => return Err(e.into()),
+                    }
                 }
-            };
-            self.all_processes = processes;
-            self.cache_time = get_current_time();
-            self.etag = etag;
+            }
+        }
+    }
+
+    /// Reloads the stored processes, skipping the download when the stored
+    /// version still matches unless `force_refresh` is set.
+    pub async fn refresh_cache(&mut self, force_refresh: bool) -> Result<(), StoreError> {
+        if !force_refresh && self.store.current_version().await? == self.etag {
+            self.view.mark_fresh();
+            return Ok(());
+        }
+        let (processes, etag) = self.store.load().await?;
+        let changed = etag != self.etag;
+        self.all_processes = processes;
+        self.etag = etag;
+        if changed {
+            self.publish().await;
             info!("Cache refreshed!");
+        } else {
+            self.view.mark_fresh();
         }
+        Ok(())
     }
 
-    pub async fn add_process(&mut self, process: Process) -> Result<(), Box<dyn std::error::Error>> {
-        self.refresh_cache(true).await;
-        match self.all_processes.entry(process.name.clone()) {
-            std::collections::hash_map::Entry::Vacant(e) => {
-                // The key does not exist, insert the new process
-                e.insert(process);
-                self.write_cache().await;
-                Ok(())
-            },
-            std::collections::hash_map::Entry::Occupied(_) => {
-                // The key already exists, return an error
-                let info = format!("Process {} already exists", process.name);
-                info!("{}", &info);
-                Err(info.into())
+    pub async fn add_process(&mut self, process: Process, ctx: &RequestContext) -> Result<(), ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            match all_processes.entry(process.name.clone()) {
+                std::collections::hash_map::Entry::Vacant(e) => {
+                    // The key does not exist, insert the new process
+                    e.insert(process.clone());
+                    Ok(())
+                },
+                std::collections::hash_map::Entry::Occupied(_) => {
+                    // The key already exists, return an error
+                    let e = ControlApiError::AlreadyExists(process.name.clone());
+                    info!("{}", e);
+                    Err(e)
+                }
             }
-        }
+        }).await
     }
 
-    pub async fn modify_process(&mut self, process: Process) -> Result<(), Box<dyn std::error::Error>> {
-        match self.all_processes.entry(process.name.clone()) {
-            std::collections::hash_map::Entry::Vacant(_) => {
-                // The key does not exist, insert the new process
-                let info = format!("Process with name {} does not exist", process.name.clone());
-                error!("{}", &info);
-                Err(info.into())
-            },
-            std::collections::hash_map::Entry::Occupied(mut e) => {
-                // The key already exists, return an error
-                e.insert(process);
-                self.write_cache().await;
-                Ok(())
+    pub async fn modify_process(&mut self, process: Process, ctx: &RequestContext) -> Result<(), ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            match all_processes.entry(process.name.clone()) {
+                std::collections::hash_map::Entry::Vacant(_) => {
+                    let e = ControlApiError::NotFound(process.name.clone());
+                    error!("{}", e);
+                    Err(e)
+                },
+                std::collections::hash_map::Entry::Occupied(mut e) => {
+                    e.insert(process.clone());
+                    Ok(())
+                }
             }
-        }
+        }).await
     }
 
-    pub async fn delete_process(&mut self, process_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        self.refresh_cache(true).await;
-        match self.all_processes.remove(process_name) {
-            Some(_) => {
-                self.write_cache().await;
-                Ok(())
-            },
-            None => {
-                let info = format!("Process with name {} does not exist", process_name);
-                error!("{}", &info);
-                Err(info.into())
+    pub async fn delete_process(&mut self, process_name: &str, ctx: &RequestContext) -> Result<(), ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            match all_processes.remove(process_name) {
+                Some(_) => Ok(()),
+                None => {
+                    let e = ControlApiError::NotFound(process_name.to_string());
+                    error!("{}", e);
+                    Err(e)
+                }
             }
-        }
+        }).await
     }
 
-    pub async fn update_process_partial(&mut self, process_name: &str, run: Option<bool>, tags: Option<Vec<String>>) -> Result<(), Box<dyn std::error::Error>> {
-        self.refresh_cache(true).await;
-        match self.all_processes.get_mut(process_name) {
-            Some(p) => {
-                update_process_partial(p, run, tags);
-                self.write_cache().await;
-                Ok(())
-            },
-            None => {
-                let info = format!("Process with name {} does not exist", process_name);
-                error!("{}", &info);
-                Err(info.into())
+    /// Deletes every process matching `filter` in one write, provided exactly
+    /// `confirm` of them match when it is applied.
+    pub async fn delete_processes(&mut self, filter: &Filter, confirm: usize, ctx: &RequestContext) -> Result<Vec<Process>, ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            let matched = all_processes.values().filter(|p| filter.matches(p)).count();
+            if matched != confirm {
+                return Err(ControlApiError::ConfirmationMismatch { confirm, matched });
             }
-        }
+            Ok(delete_processes(all_processes, filter))
+        }).await
+    }
+
+    /// What `delete_processes` would delete, without saving anything.
+    pub async fn preview_delete_processes(&mut self, filter: &Filter) -> Vec<ProcessChange> {
+        self.preview(|all_processes| { delete_processes(all_processes, filter); }).await
     }
 
-    pub async fn merge_processes(&mut self, process_inputs: Vec<ProcessPatchInput>) -> Result<Vec<ProcessMessage>, Box<dyn std::error::Error>> {
-        self.refresh_cache(true).await;
-        let mut process_messages: Vec<ProcessMessage> = Vec::new();
-        for process_input in process_inputs {
-            match self.all_processes.get_mut(&process_input.name) {
+    /// Moves process `name` to `new_name` in one write, keeping its run state,
+    /// tags and schedule, and recording it as `renamed` so its history
+    /// follows it. Processes that depended on it depend on the new name.
+    pub async fn rename_process(&mut self, name: &str, new_name: &str, ctx: &RequestContext) -> Result<Process, ControlApiError> {
+        self.mutate_audited(ctx, |all_processes| {
+            if all_processes.contains_key(new_name) {
+                return Err(ControlApiError::AlreadyExists(new_name.to_string()));
+            }
+            let mut process = all_processes.remove(name).ok_or_else(|| ControlApiError::NotFound(name.to_string()))?;
+            process.name = new_name.to_string();
+            for dependency in all_processes.values_mut().filter_map(|p| p.depends_on.as_mut()).flatten() {
+                if dependency == name {
+                    *dependency = new_name.to_string();
+                }
+            }
+            all_processes.insert(new_name.to_string(), process.clone());
+            Ok(process)
+        }, |entries| audit::renamed(entries, name, new_name)).await
+    }
+
+    pub async fn update_process_partial(&mut self, input: &ProcessPatchInput, ctx: &RequestContext) -> Result<(), ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            match all_processes.get_mut(&input.name) {
                 Some(p) => {
-                    update_process_partial(p, process_input.run, process_input.tags);
-                    process_messages.push(ProcessMessage {
-                        name: process_input.name.clone(),
-                        action: "Updated".to_string(),
-                    });
+                    update_process_partial(p, input);
+                    Ok(())
                 },
                 None => {
-                    let n_run = match process_input.run {
-                        Some(run) => run,
-                        None => false,
-                    };
-                    let p = create_process(&process_input.name, n_run, process_input.tags);
-                    self.all_processes.insert(p.name.clone(), p);
-                    process_messages.push(ProcessMessage {
-                        name: process_input.name.clone(),
-                        action: "Added".to_string(),
-                    });
+                    let e = ControlApiError::NotFound(input.name.clone());
+                    error!("{}", e);
+                    Err(e)
                 }
             }
-        }
-        self.write_cache().await;
-        Ok(process_messages)
+        }).await
+    }
+
+    pub async fn merge_processes(&mut self, process_inputs: Vec<ProcessPatchInput>, ctx: &RequestContext) -> Result<Vec<ProcessMessage>, ControlApiError> {
+        self.mutate(ctx, |all_processes| Ok(merge_processes(all_processes, &process_inputs))).await
+    }
+
+    /// What `merge_processes` would change, without saving anything.
+    pub async fn preview_merge_processes(&mut self, process_inputs: &[ProcessPatchInput]) -> Vec<ProcessChange> {
+        self.preview(|all_processes| { merge_processes(all_processes, process_inputs); }).await
+    }
+
+    /// Restores the document saved in `version_id` with the same conditional
+    /// write as any other change, returning what it changed.
+    pub async fn rollback(&mut self, version_id: &str, ctx: &RequestContext) -> Result<Vec<ProcessChange>, ControlApiError> {
+        let restored = self.store.load_version(version_id).await?;
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        self.mutate(ctx, |all_processes| {
+            let mut restored = restored.clone();
+            // Restoring a run state is an explicit change, so it wins over transitions already due.
+            for p in restored.values_mut() {
+                if all_processes.get(&p.name).is_none_or(|current| current.run != p.run) {
+                    p.effective = now.clone();
+                    p.run_set_at = Some(now.clone());
+                }
+            }
+            let changes = audit::diff(all_processes, &restored);
+            *all_processes = restored;
+            Ok(changes)
+        }).await
     }
 
     pub fn get_process(&self, process_name: &str) -> Option<Process> {
-        self.all_processes.get(process_name).map(|p| p.clone())
+        self.all_processes.get(process_name).cloned()
     }
 
-    pub fn filter_processes(&self, query: &ProcessQueryParams) -> Vec<Process> {
-        let processes = to_list(&self.all_processes);
-        filter_processes(processes, query)
+    pub fn filter_processes(&self, filter: &Filter) -> Vec<Process> {
+        filter_processes(to_list(&self.all_processes), filter)
     }
 
-    pub fn filter_processes_pattern(&self, query: &ProcessQuery) -> Vec<Process> {
-        let processes = to_list(&self.all_processes);
-        filter_processes_pattern(processes, query)
+    pub async fn control_processes(&mut self, filter: &Filter, run: bool, reason: &Option<String>, ctx: &RequestContext) -> Result<Vec<Process>, ControlApiError> {
+        self.mutate(ctx, |all_processes| Ok(control_processes(all_processes, filter, run, reason))).await
     }
 
-    pub async fn control_processes(&mut self, query: &ProcessQuery, run: bool) -> Result<(Vec<Process>), Box<dyn std::error::Error>> {
-        self.refresh_cache(true).await;
-        let processes = self.filter_processes_pattern(query);
-        let mut updated_processes: Vec<Process> = Vec::new();
-        for process in processes {
-            let process_name = process.name.clone();
-            let p = self.all_processes.get_mut(&process_name).unwrap();
-            update_process_partial(p, Some(run), None);
-            updated_processes.push(p.clone());
+    /// Removes `remove` from and adds `add` to the tags of process `name`.
+    pub async fn change_process_tags(&mut self, name: &str, add: &[String], remove: &[String], ctx: &RequestContext) -> Result<Process, ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            if !all_processes.contains_key(name) {
+                return Err(ControlApiError::NotFound(name.to_string()));
+            }
+            Ok(tags::apply(all_processes, &[name.to_string()], add, remove)?.remove(0))
+        }).await
+    }
+
+    /// Removes `remove` from and adds `add` to the tags of every process
+    /// matching `filter`, returning them by name.
+    pub async fn change_tags(&mut self, filter: &Filter, add: &[String], remove: &[String], ctx: &RequestContext) -> Result<Vec<Process>, ControlApiError> {
+        self.mutate(ctx, |all_processes| tags::apply(all_processes, &matching_names(all_processes, filter), add, remove)).await
+    }
+
+    /// What `change_tags` would change, without saving anything.
+    pub async fn preview_change_tags(&mut self, filter: &Filter, add: &[String], remove: &[String]) -> Result<Vec<ProcessChange>, ControlApiError> {
+        let mut result = Ok(Vec::new());
+        let changes = self.preview(|all_processes| {
+            result = tags::apply(all_processes, &matching_names(all_processes, filter), add, remove);
+        }).await;
+        result.map(|_| changes)
+    }
+
+    /// Sets `run` on the processes named in `names`, skipping ones that no longer exist.
+    pub async fn control_named(&mut self, names: &[String], run: bool, reason: &Option<String>, ctx: &RequestContext) -> Result<(), ControlApiError> {
+        self.mutate(ctx, |all_processes| {
+            for name in names {
+                if let Some(p) = all_processes.get_mut(name) {
+                    update_process_partial(p, &ProcessPatchInput { name: name.clone(), run: Some(run), reason: reason.clone(), ..Default::default() });
+                }
+            }
+            Ok(())
+        }).await
+    }
+
+    /// What `control_processes` would change, without saving anything.
+    pub async fn preview_control_processes(&mut self, filter: &Filter, run: bool, reason: &Option<String>) -> Vec<ProcessChange> {
+        self.preview(|all_processes| { control_processes(all_processes, filter, run, reason); }).await
+    }
+
+    /// Applies `op` to a copy of the latest processes and lists the changes
+    /// it makes, naming a flipped `run` as `started` or `stopped`. Processes
+    /// `op` only touches without changing them are left out.
+    async fn preview<F>(&mut self, op: F) -> Vec<ProcessChange>
+    where
+        F: FnOnce(&mut HashMap<String, Process>),
+    {
+        // A preview saves nothing, so the cached processes will do when the store can't be read.
+        if let Err(e) = self.refresh_cache(true).await {
+            error!("Error reading cache: {:?}", e);
         }
-        self.write_cache().await;
-        Ok(updated_processes)
+        let mut processes = self.all_processes.clone();
+        op(&mut processes);
+        audit::diff(&self.all_processes, &processes).into_iter()
+            .filter(|change| change.previous != change.new)
+            .map(|mut change| {
+                if let (Some(previous), Some(new)) = (&change.previous, &change.new) {
+                    if previous.run != new.run {
+                        change.action = if new.run { "started" } else { "stopped" }.to_string();
+                    }
+                }
+                change
+            })
+            .collect()
     }
 }
 
-pub async fn read_process(process_name: &str) -> Option<Process> {
-    let singleton = MyCache::get_instance().await.lock().unwrap();
-    match singleton.get_process(process_name) {
-        Some(p) => Some(p),
-        None => None
+/// Keeps the cache in step with the store every `interval`, so requests
+/// never wait for a refresh themselves.
+pub fn spawn_refresher(cache: Arc<Mutex<MyCache>>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = cache.lock().await.refresh_cache(false).await {
+                METRICS.refresh_failed();
+                error!("Error refreshing cache: {:?}", e);
+            }
+        }
+    });
+}
+
+/// A patch that stops `name`.
+#[cfg(test)]
+pub fn stop_input(name: &str) -> ProcessPatchInput {
+    ProcessPatchInput { name: name.to_string(), run: Some(false), ..Default::default() }
+}
+
+/// Lets another "replica" write process2 just before the first save lands.
+#[cfg(test)]
+struct RacingStore {
+    inner: crate::store::MemoryStore,
+    raced: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl ProcessStore for RacingStore {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        self.inner.load().await
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        self.inner.current_version().await
+    }
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError> {
+        if !self.raced.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            let (mut other, other_version) = self.inner.load().await?;
+            other.insert("process2".to_string(), create_process("process2", false, None));
+            self.inner.save(&other, &other_version).await?;
+        }
+        self.inner.save(data, version).await
+    }
+
+    async fn append_audit(&self, entries: &[crate::audit::AuditEntry]) -> Result<(), StoreError> {
+        self.inner.append_audit(entries).await
     }
+
+    async fn load_audit(&self, process_name: &str, days: &crate::store::DayRange) -> Result<Vec<crate::audit::AuditEntry>, StoreError> {
+        self.inner.load_audit(process_name, days).await
+    }
+
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+}
+
+#[tokio::test]
+async fn test_mutation_reapplied_after_concurrent_write() {
+    let store: Arc<dyn ProcessStore> = Arc::new(RacingStore {
+        inner: crate::store::MemoryStore::new(HashMap::new()),
+        raced: std::sync::atomic::AtomicBool::new(false),
+    });
+    let mut cache = MyCache::new(store.clone(), 0, None).await.unwrap();
+    cache.add_process(create_process("process1", true, None), &RequestContext::new("alice", "req-1")).await.unwrap();
+
+    let (stored, _) = store.load().await.unwrap();
+    assert!(stored.contains_key("process1") && stored.contains_key("process2"));
+    assert_eq!(cache.all_processes.len(), 2);
+}
+
+#[cfg(test)]
+struct UnavailableStore;
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl ProcessStore for UnavailableStore {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        Ok((HashMap::new(), "1".to_string()))
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        Ok("1".to_string())
+    }
+
+    async fn save(&self, _data: &HashMap<String, Process>, _version: &str) -> Result<String, StoreError> {
+        Err(StoreError::Backend("connection refused".to_string()))
+    }
+
+    async fn append_audit(&self, _entries: &[crate::audit::AuditEntry]) -> Result<(), StoreError> {
+        Err(StoreError::Backend("connection refused".to_string()))
+    }
+
+    async fn load_audit(&self, _process_name: &str, _days: &crate::store::DayRange) -> Result<Vec<crate::audit::AuditEntry>, StoreError> {
+        Err(StoreError::Backend("connection refused".to_string()))
+    }
+
+    fn describe(&self) -> String {
+        "unavailable://".to_string()
+    }
+}
+
+/// Saves like `MemoryStore`, but loses every audit entry.
+#[cfg(test)]
+struct UnauditedStore(crate::store::MemoryStore);
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl ProcessStore for UnauditedStore {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        self.0.load().await
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        self.0.current_version().await
+    }
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError> {
+        self.0.save(data, version).await
+    }
+
+    async fn append_audit(&self, _entries: &[crate::audit::AuditEntry]) -> Result<(), StoreError> {
+        Err(StoreError::Backend("connection refused".to_string()))
+    }
+
+    async fn load_audit(&self, process_name: &str, days: &crate::store::DayRange) -> Result<Vec<crate::audit::AuditEntry>, StoreError> {
+        self.0.load_audit(process_name, days).await
+    }
+
+    fn describe(&self) -> String {
+        self.0.describe()
+    }
+}
+
+#[tokio::test]
+async fn test_lost_audit_entries_are_reported() {
+    let mut cache = MyCache::new(Arc::new(UnauditedStore(crate::store::MemoryStore::new(HashMap::new()))), 0, None).await.unwrap();
+    let ctx = RequestContext::new("alice", "req-1");
+    cache.add_process(create_process("process1", true, None), &ctx).await.unwrap();
+    assert!(ctx.is_unrecorded());
+    assert!(cache.get_process("process1").is_some());
+}
+
+#[tokio::test]
+async fn test_failed_write_rolls_back_cache() {
+    let mut cache = MyCache::new(Arc::new(UnavailableStore), 0, None).await.unwrap();
+    let err = cache.add_process(create_process("process1", true, None), &RequestContext::new("alice", "req-1")).await.unwrap_err();
+    assert!(matches!(err, ControlApiError::StorageUnavailable(_)));
+    assert!(cache.get_process("process1").is_none());
+}
+
+#[tokio::test]
+async fn test_mutations_are_audited() {
+    let mut cache = MyCache::new(Arc::new(crate::store::MemoryStore::new(HashMap::new())), 0, None).await.unwrap();
+    cache.add_process(create_process("process1", true, None), &RequestContext::new("alice", "req-1")).await.unwrap();
+    cache.update_process_partial(&stop_input("process1"), &RequestContext::new("bob", "req-2")).await.unwrap();
+
+    let history = cache.store.load_audit("process1", &Default::default()).await.unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!((history[1].caller.as_str(), history[1].request_id.as_str()), ("bob", "req-2"));
+    assert!(history[1].previous.as_ref().unwrap().run);
+    assert!(!history[1].new.as_ref().unwrap().run);
+}
+
+#[test]
+fn test_view_refuses_stale_reads() {
+    let view = ProcessView::new(HashMap::from([("process1".to_string(), create_process("process1", true, None))]), "1", 60);
+    assert!(view.get_process("process1").unwrap().is_some());
+    view.refreshed_at.store(get_current_time() - 61, Ordering::Relaxed);
+    assert!(matches!(view.get_process("process1"), Err(ControlApiError::StorageUnavailable(_))));
+    view.publish(HashMap::new(), "2");
+    assert!(view.get_process("process1").unwrap().is_none());
+}
+
+/// A store that can be taken down and brought back.
+#[cfg(test)]
+struct FlakyStore {
+    inner: crate::store::MemoryStore,
+    down: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl FlakyStore {
+    fn check(&self) -> Result<(), StoreError> {
+        match self.down.load(Ordering::SeqCst) {
+            true => Err(StoreError::Backend("dispatch failure".to_string())),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl ProcessStore for FlakyStore {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        self.check()?;
+        self.inner.load().await
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        self.check()?;
+        self.inner.current_version().await
+    }
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError> {
+        self.check()?;
+        self.inner.save(data, version).await
+    }
+
+    async fn append_audit(&self, entries: &[crate::audit::AuditEntry]) -> Result<(), StoreError> {
+        self.inner.append_audit(entries).await
+    }
+
+    async fn load_audit(&self, process_name: &str, days: &crate::store::DayRange) -> Result<Vec<crate::audit::AuditEntry>, StoreError> {
+        self.inner.load_audit(process_name, days).await
+    }
+
+    fn describe(&self) -> String {
+        "flaky://".to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_degraded_start_from_snapshot() {
+    let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+    let snapshot = || Some(LastKnownGood::new(path.to_str().unwrap()));
+    let store = Arc::new(FlakyStore {
+        inner: crate::store::MemoryStore::new(HashMap::new()),
+        down: std::sync::atomic::AtomicBool::new(false),
+    });
+    let ctx = RequestContext::new("alice", "req-1");
+    let mut cache = MyCache::new(store.clone(), 60, snapshot()).await.unwrap();
+    cache.add_process(create_process("process1", true, None), &ctx).await.unwrap();
+
+    store.down.store(true, Ordering::SeqCst);
+    let mut cache = MyCache::new(store.clone(), 60, snapshot()).await.unwrap();
+    let view = cache.view();
+    assert!(view.is_degraded());
+    assert!(view.get_process("process1").unwrap().is_some());
+    let err = cache.update_process_partial(&stop_input("process1"), &ctx).await.unwrap_err();
+    assert!(matches!(err, ControlApiError::StorageUnavailable(_)));
+
+    store.down.store(false, Ordering::SeqCst);
+    cache.refresh_cache(false).await.unwrap();
+    assert!(!view.is_degraded());
+    cache.update_process_partial(&stop_input("process1"), &ctx).await.unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_rollback_restores_version() {
+    let store = Arc::new(crate::store::MemoryStore::new(HashMap::new()));
+    let mut cache = MyCache::new(store.clone(), 0, None).await.unwrap();
+    let ctx = RequestContext::new("alice", "req-1");
+    cache.add_process(create_process("process1", true, None), &ctx).await.unwrap();
+    let good = store.list_versions().await.unwrap()[0].version_id.clone();
+    cache.update_process_partial(&stop_input("process1"), &ctx).await.unwrap();
+    cache.add_process(create_process("process2", true, None), &ctx).await.unwrap();
+
+    let changes = cache.rollback(&good, &RequestContext::new("bob", "req-2")).await.unwrap();
+    let actions: Vec<(&str, &str)> = changes.iter().map(|c| (c.process_name.as_str(), c.action.as_str())).collect();
+    assert_eq!(actions, vec![("process1", "updated"), ("process2", "deleted")]);
+    assert!(cache.get_process("process1").unwrap().run);
+    assert_eq!(store.load_audit("process2", &Default::default()).await.unwrap().last().unwrap().caller, "bob");
+    assert!(matches!(cache.rollback("99", &ctx).await, Err(ControlApiError::VersionNotFound(_))));
+}
+
+#[tokio::test]
+async fn test_preview_saves_nothing() {
+    let store = Arc::new(crate::store::MemoryStore::new(HashMap::new()));
+    let mut cache = MyCache::new(store.clone(), 0, None).await.unwrap();
+    let ctx = RequestContext::new("alice", "req-1");
+    cache.add_process(create_process("process1", true, None), &ctx).await.unwrap();
+    cache.add_process(create_process("process2", false, None), &ctx).await.unwrap();
+    let version = store.current_version().await.unwrap();
+
+    let filter = Filter::name_patterns(&["process*".to_string()]).unwrap();
+    let changes = cache.preview_control_processes(&filter, false, &None).await;
+    let actions: Vec<(&str, &str)> = changes.iter().map(|c| (c.process_name.as_str(), c.action.as_str())).collect();
+    assert_eq!(actions, vec![("process1", "stopped")]);
+
+    let inputs = vec![ProcessPatchInput { name: "procss3".to_string(), ..Default::default() }];
+    assert_eq!(cache.preview_merge_processes(&inputs).await[0].action, "added");
+    assert_eq!(store.current_version().await.unwrap(), version);
+    assert!(cache.get_process("process1").unwrap().run);
+}
+
+#[test]
+fn test_metadata_round_trip() {
+    // Documents written before metadata existed still load.
+    let mut process: Process = serde_json::from_str(r#"{"name":"process1","run":true,"tags":null,"effective":"2024-03-01 12:00:00"}"#).unwrap();
+    assert!(process.metadata.is_empty() && process.reason.is_none());
+
+    let patch: ProcessPatchInput = serde_json::from_str(
+        r#"{"name":"process1","run":false,"reason":"INC-42","owner":"alice","labels":{"env":"prod"}}"#
+    ).unwrap();
+    update_process_partial(&mut process, &patch);
+    let json = serde_json::to_string(&process).unwrap();
+    assert!(json.contains(r#""owner":"alice","labels":{"env":"prod"},"reason":"INC-42""#));
+    assert_eq!(serde_json::from_str::<Process>(&json).unwrap(), process);
+    assert!(crate::query::Filter::parse("label:env=prod owner:alice reason~^INC").unwrap().matches(&process));
+
+    // Clearing the owner, and changing metadata alone, keeps the reason for the last run change.
+    update_process_partial(&mut process, &serde_json::from_str(r#"{"name":"process1","owner":""}"#).unwrap());
+    assert!(process.metadata.owner.is_none() && process.reason.as_deref() == Some("INC-42"));
+}
+
+#[tokio::test]
+async fn test_bulk_delete_and_rename() {
+    let store = Arc::new(crate::store::MemoryStore::new(HashMap::new()));
+    let mut cache = MyCache::new(store.clone(), 0, None).await.unwrap();
+    let ctx = RequestContext::new("alice", "req-1");
+    for name in ["etl-load", "etl-clean", "api"] {
+        cache.add_process(create_process(name, true, None), &ctx).await.unwrap();
+    }
+    let mut api = create_process("api", false, Some(vec!["web".to_string()]));
+    api.depends_on = Some(vec!["etl-load".to_string()]);
+    cache.modify_process(api, &ctx).await.unwrap();
+
+    let renamed = cache.rename_process("etl-load", "etl-ingest", &ctx).await.unwrap();
+    assert!(renamed.run && cache.get_process("etl-load").is_none());
+    assert_eq!(cache.get_process("api").unwrap().depends_on, Some(vec!["etl-ingest".to_string()]));
+    let history = audit::history(store.as_ref(), "etl-ingest", &Default::default()).await.unwrap();
+    let actions: Vec<&str> = history.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, vec!["added", "renamed"]);
+    assert_eq!(history[1].renamed_from.as_deref(), Some("etl-load"));
+    assert!(matches!(cache.rename_process("api", "etl-clean", &ctx).await, Err(ControlApiError::AlreadyExists(_))));
+
+    // A process named before the naming rule can be renamed into it.
+    cache.add_process(create_process("legacy api", true, None), &ctx).await.unwrap();
+    let rename = crate::ProcessRename { name: "legacy api".to_string(), new_name: "legacy-api".to_string() };
+    assert!(rename.validate().is_ok());
+    assert!(crate::ProcessRename { name: "legacy-api".to_string(), new_name: "legacy api".to_string() }.validate().is_err());
+    assert!(cache.rename_process(&rename.name, &rename.new_name, &ctx).await.unwrap().run);
+    cache.delete_process("legacy-api", &ctx).await.unwrap();
+
+    let filter = Filter::name_patterns(&["etl-*".to_string()]).unwrap();
+    assert_eq!(cache.preview_delete_processes(&filter).await.len(), 2);
+    let saves = store.list_versions().await.unwrap().len();
+    let err = cache.delete_processes(&filter, 3, &ctx).await.unwrap_err();
+    assert!(matches!(err, ControlApiError::ConfirmationMismatch { confirm: 3, matched: 2 }));
+    assert_eq!(store.list_versions().await.unwrap().len(), saves);
+    let deleted = cache.delete_processes(&filter, 2, &ctx).await.unwrap();
+    let names: Vec<&str> = deleted.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["etl-clean", "etl-ingest"]);
+    assert_eq!(store.list_versions().await.unwrap().len(), saves + 1);
+}
+
+#[tokio::test]
+async fn test_history_of_a_name_renamed_back() {
+    let store = Arc::new(crate::store::MemoryStore::new(HashMap::new()));
+    let mut cache = MyCache::new(store.clone(), 0, None).await.unwrap();
+    cache.add_process(create_process("etl", true, None), &RequestContext::new("alice", "req-1")).await.unwrap();
+    cache.rename_process("etl", "etl-v2", &RequestContext::new("alice", "req-2")).await.unwrap();
+    cache.rename_process("etl-v2", "etl", &RequestContext::new("alice", "req-3")).await.unwrap();
+
+    let history = audit::history(store.as_ref(), "etl", &Default::default()).await.unwrap();
+    let steps: Vec<(&str, &str)> = history.iter().map(|e| (e.request_id.as_str(), e.action.as_str())).collect();
+    assert_eq!(steps, vec![("req-1", "added"), ("req-2", "renamed"), ("req-3", "renamed")]);
+}
+
+#[tokio::test]
+async fn test_edits_keep_due_transitions() {
+    use consumer_control_client::schedule::{Schedule, Transition};
+
+    let mut cache = MyCache::new(Arc::new(crate::store::MemoryStore::new(HashMap::new())), 0, None).await.unwrap();
+    let ctx = RequestContext::new("alice", "req-1");
+    let mut process = create_process("process1", true, None);
+    process.run_set_at = Some((Local::now() - chrono::Duration::hours(2)).format("%Y-%m-%d %H:%M:%S").to_string());
+    process.schedule = Some(Schedule {
+        transitions: vec![Transition { at: (Utc::now() - chrono::Duration::hours(1)).fixed_offset(), run: false }],
+        windows: Vec::new(),
+    });
+    cache.add_process(process, &ctx).await.unwrap();
+    assert!(!cache.get_process("process1").unwrap().resolved(Utc::now()).run);
+
+    // As PATCH /process/tags does; only changing run explicitly overrides the stop.
+    cache.change_process_tags("process1", &["v4".to_string()], &[], &ctx).await.unwrap();
+    assert!(!cache.get_process("process1").unwrap().resolved(Utc::now()).run);
+    cache.update_process_partial(&ProcessPatchInput { name: "process1".to_string(), run: Some(true), ..Default::default() }, &ctx).await.unwrap();
+    assert!(cache.get_process("process1").unwrap().resolved(Utc::now()).run);
 }
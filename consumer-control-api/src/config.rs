@@ -0,0 +1,230 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+/// Command line flags. Each one can also be set through the environment
+/// variable named in its help, and overrides the configuration file.
+#[derive(Parser, Debug, Default)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// TOML configuration file; flags and environment variables override its values.
+    #[arg(short, long, env = "CONTROL_API_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration as TOML and exit.
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Address to listen on.
+    #[arg(long, env = "CONTROL_API_HOST")]
+    pub host: Option<String>,
+
+    #[arg(short, long, env = "CONTROL_API_PORT")]
+    pub port: Option<u16>,
+
+    /// Directory served under /docs, holding openapi.yml and openapi.html.
+    #[arg(long, env = "DOCS_DIR")]
+    pub docs_dir: Option<PathBuf>,
+
+    #[arg(long, env = "CONTROL_API_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Where processes are stored: s3://bucket/key, a local file path or memory://.
+    #[arg(short, long, env = "s3_file")]
+    pub store: Option<String>,
+
+    /// Seconds between background checks of the store for changes made by other replicas.
+    #[arg(long, env = "CONTROL_API_REFRESH_INTERVAL_SECS")]
+    pub refresh_interval_secs: Option<u64>,
+
+    /// Reads fail with 503 once the processes have not been refreshed for
+    /// this many seconds; 0 serves them however old they are.
+    #[arg(long, env = "CONTROL_API_MAX_STALENESS_SECS")]
+    pub max_staleness_secs: Option<u64>,
+
+    /// Local file keeping the last document read from the store, used to
+    /// start in degraded mode when the store is unreachable at startup.
+    /// Namespaces from the namespaces file name their own.
+    #[arg(long, env = "CONTROL_API_SNAPSHOT_FILE")]
+    pub snapshot_file: Option<PathBuf>,
+
+    /// JSON file of `{"<namespace>": {"store": ..., "snapshot_file": ...}}`
+    /// served under /ns/{namespace}. The store above, if any, is the
+    /// `default` namespace served without the prefix.
+    #[arg(long, env = "CONTROL_API_NAMESPACES_FILE")]
+    pub namespaces_file: Option<PathBuf>,
+
+    /// /readyz fails once the processes have not been refreshed for this many seconds.
+    #[arg(long, env = "CONTROL_API_READY_MAX_AGE_SECS")]
+    pub ready_max_age_secs: Option<u64>,
+
+    /// JSON file of static API keys, each with a name, key and role.
+    #[arg(long, env = "CONTROL_API_API_KEYS_FILE")]
+    pub api_keys_file: Option<PathBuf>,
+
+    /// JSON Web Key Set of symmetric keys used to verify HS256 bearer tokens.
+    #[arg(long, env = "CONTROL_API_JWT_KEYS_FILE")]
+    pub jwt_keys_file: Option<PathBuf>,
+
+    /// Let callers without credentials use the read-only endpoints.
+    #[arg(long, env = "CONTROL_API_ALLOW_ANONYMOUS_READS", num_args = 0..=1, default_missing_value = "true")]
+    pub allow_anonymous_reads: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+/// The effective configuration, validated once at startup.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub docs_dir: PathBuf,
+    pub log_format: LogFormat,
+    pub ready_max_age_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "0.0.0.0".to_string(),
+            port: 3000,
+            docs_dir: PathBuf::from("./docs"),
+            log_format: LogFormat::Text,
+            ready_max_age_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub location: Option<String>,
+    pub refresh_interval_secs: u64,
+    pub max_staleness_secs: u64,
+    pub snapshot_file: Option<PathBuf>,
+    pub namespaces_file: Option<PathBuf>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            location: None,
+            refresh_interval_secs: 10,
+            max_staleness_secs: 300,
+            snapshot_file: None,
+            namespaces_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub api_keys_file: Option<PathBuf>,
+    pub jwt_keys_file: Option<PathBuf>,
+    pub allow_anonymous_reads: bool,
+}
+
+impl Config {
+    /// Reads the configuration file named in `args`, if any, and applies the
+    /// flags and environment variables on top of it.
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read configuration file {}: {}", path.display(), e))?;
+                toml::from_str(&contents)
+                    .map_err(|e| format!("Invalid configuration file {}: {}", path.display(), e))?
+            },
+            None => Config::default(),
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, args: &Args) {
+        fn set<T: Clone>(slot: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *slot = value.clone();
+            }
+        }
+        fn set_some<T: Clone>(slot: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                *slot = value.clone();
+            }
+        }
+        set(&mut self.server.host, &args.host);
+        set(&mut self.server.port, &args.port);
+        set(&mut self.server.docs_dir, &args.docs_dir);
+        set(&mut self.server.log_format, &args.log_format);
+        set(&mut self.server.ready_max_age_secs, &args.ready_max_age_secs);
+        set_some(&mut self.storage.location, &args.store);
+        set(&mut self.storage.refresh_interval_secs, &args.refresh_interval_secs);
+        set(&mut self.storage.max_staleness_secs, &args.max_staleness_secs);
+        set_some(&mut self.storage.snapshot_file, &args.snapshot_file);
+        set_some(&mut self.storage.namespaces_file, &args.namespaces_file);
+        set_some(&mut self.auth.api_keys_file, &args.api_keys_file);
+        set_some(&mut self.auth.jwt_keys_file, &args.jwt_keys_file);
+        set(&mut self.auth.allow_anonymous_reads, &args.allow_anonymous_reads);
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.storage.location.is_none() && self.storage.namespaces_file.is_none() {
+            return Err("storage.location (--store, s3_file) or storage.namespaces_file must be set".to_string());
+        }
+        if self.storage.refresh_interval_secs == 0 {
+            return Err("storage.refresh_interval_secs must be at least 1".to_string());
+        }
+        for path in [&self.storage.namespaces_file, &self.auth.api_keys_file, &self.auth.jwt_keys_file].into_iter().flatten() {
+            if !path.is_file() {
+                return Err(format!("{} does not exist", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.server.host, self.server.port)
+    }
+}
+
+#[test]
+fn test_flags_override_file() {
+    let mut config: Config = toml::from_str(r#"
+        [server]
+        port = 8080
+        log_format = "json"
+
+        [storage]
+        location = "memory://"
+        refresh_interval_secs = 30
+    "#).unwrap();
+    config.apply(&Args { port: Some(9090), store: Some("/tmp/processes.json".to_string()), ..Args::default() });
+
+    assert_eq!(config.bind_address(), "0.0.0.0:9090");
+    assert_eq!(config.server.log_format, LogFormat::Json);
+    assert_eq!(config.storage.location.as_deref(), Some("/tmp/processes.json"));
+    assert_eq!(config.storage.refresh_interval_secs, 30);
+    assert_eq!(config.storage.max_staleness_secs, 300);
+    assert!(config.validate().is_ok());
+
+    assert!(toml::from_str::<Config>("[storage]\nlocaton = \"memory://\"").is_err());
+}
@@ -0,0 +1,148 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use thiserror::Error;
+
+use crate::cache::Process;
+use crate::store::StoreError;
+
+pub use consumer_control_client::model::GenericErrorResponse;
+use consumer_control_client::validation::FieldError;
+
+/// Every error the API returns. Each variant maps to one HTTP status and one
+/// stable `error` code in the JSON body, which clients should match on
+/// instead of the human readable `message`.
+#[derive(Debug, Error)]
+pub enum ControlApiError {
+    #[error("Process with name {0} does not exist")]
+    NotFound(String),
+    #[error("Process {0} already exists")]
+    AlreadyExists(String),
+    #[error("Version {0} of the processes does not exist")]
+    VersionNotFound(String),
+    #[error("Namespace {0} does not exist")]
+    NamespaceNotFound(String),
+    #[error("No process belongs to group {0}")]
+    GroupNotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Validation(String),
+    /// Fields of the request body that break a validation rule.
+    #[error("Invalid request: {}", describe(.0))]
+    InvalidFields(Vec<FieldError>),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("confirm was {confirm} but {matched} processes match; nothing was deleted")]
+    ConfirmationMismatch { confirm: usize, matched: usize },
+    #[error("{source}")]
+    Conflict {
+        source: StoreError,
+        current: Vec<Process>,
+    },
+    #[error("changes were not saved: {0}")]
+    StorageUnavailable(StoreError),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ControlApiError {
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            ControlApiError::NotFound(_) => "process_not_found",
+            ControlApiError::AlreadyExists(_) => "process_already_exists",
+            ControlApiError::VersionNotFound(_) => "version_not_found",
+            ControlApiError::NamespaceNotFound(_) => "namespace_not_found",
+            ControlApiError::GroupNotFound(_) => "group_not_found",
+            ControlApiError::BadRequest(_) => "bad_request",
+            ControlApiError::Validation(_) | ControlApiError::InvalidFields(_) => "validation_failed",
+            ControlApiError::Unauthorized(_) => "unauthorized",
+            ControlApiError::Forbidden(_) => "forbidden",
+            ControlApiError::ConfirmationMismatch { .. } => "confirmation_mismatch",
+            ControlApiError::Conflict { .. } => "write_conflict",
+            ControlApiError::StorageUnavailable(_) => "storage_unavailable",
+            ControlApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Attaches the current stored state of the processes a request touched
+    /// to a write conflict, so the caller can decide whether to re-apply its change.
+    pub fn with_current(self, mut processes: Vec<Process>) -> Self {
+        match self {
+            ControlApiError::Conflict { source, .. } => {
+                processes.sort_by_key(|p| p.name.clone());
+                ControlApiError::Conflict { source, current: processes }
+            },
+            other => other,
+        }
+    }
+}
+
+fn describe(fields: &[FieldError]) -> String {
+    fields.iter().map(|e| format!("{} {}", e.field, e.message)).collect::<Vec<_>>().join("; ")
+}
+
+impl From<StoreError> for ControlApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Conflict(_) => ControlApiError::Conflict { source: e, current: Vec::new() },
+            StoreError::Backend(_) | StoreError::Io(_) => ControlApiError::StorageUnavailable(e),
+            StoreError::VersionNotFound(version_id) => ControlApiError::VersionNotFound(version_id),
+            _ => ControlApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl ResponseError for ControlApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ControlApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ControlApiError::AlreadyExists(_) => StatusCode::CONFLICT,
+            ControlApiError::VersionNotFound(_) => StatusCode::NOT_FOUND,
+            ControlApiError::NamespaceNotFound(_) => StatusCode::NOT_FOUND,
+            ControlApiError::GroupNotFound(_) => StatusCode::NOT_FOUND,
+            ControlApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ControlApiError::Validation(_) | ControlApiError::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ControlApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ControlApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ControlApiError::ConfirmationMismatch { .. } => StatusCode::CONFLICT,
+            ControlApiError::Conflict { .. } => StatusCode::CONFLICT,
+            ControlApiError::StorageUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ControlApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let current = match self {
+            ControlApiError::Conflict { current, .. } => Some(current.clone()),
+            _ => None,
+        };
+        let fields = match self {
+            ControlApiError::InvalidFields(fields) => Some(fields.clone()),
+            _ => None,
+        };
+        let error_response = GenericErrorResponse {
+            code: self.status_code().as_u16() as u32,
+            error: self.error_code().to_string(),
+            message: self.to_string(),
+            current,
+            fields,
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let ControlApiError::Unauthorized(_) = self {
+            response.insert_header((actix_web::http::header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(error_response)
+    }
+}
+
+#[test]
+fn test_store_errors_map_to_status() {
+    let conflict: ControlApiError = StoreError::Conflict("\"abc\"".to_string()).into();
+    assert_eq!(conflict.status_code(), StatusCode::CONFLICT);
+    assert_eq!(conflict.error_code(), "write_conflict");
+    let outage: ControlApiError = StoreError::Backend("dispatch failure".to_string()).into();
+    assert_eq!(outage.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(ControlApiError::NotFound("process1".to_string()).status_code(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::error;
+use tokio::sync::Mutex;
+
+use crate::audit::RequestContext;
+use crate::cache::{MyCache, Process};
+use crate::error::ControlApiError;
+
+pub use consumer_control_client::model::{GroupReport, StepStatus};
+
+/// Longest pause allowed between the steps of a group change.
+pub const MAX_INTERVAL_SECS: u64 = 600;
+
+/// The dependencies among the members of each group, ignoring processes
+/// named in `depends_on` that are not in the same group.
+fn graphs(processes: &HashMap<String, Process>) -> BTreeMap<&str, BTreeMap<&str, BTreeSet<&str>>> {
+    let mut groups: BTreeMap<&str, BTreeMap<&str, BTreeSet<&str>>> = BTreeMap::new();
+    for p in processes.values() {
+        if let Some(group) = &p.group {
+            groups.entry(group).or_default().insert(&p.name, BTreeSet::new());
+        }
+    }
+    for p in processes.values() {
+        if let Some(members) = p.group.as_deref().and_then(|g| groups.get_mut(g)) {
+            let dependencies: BTreeSet<&str> = p.depends_on.iter().flatten()
+                .map(|d| d.as_str())
+                .filter(|d| members.contains_key(d))
+                .collect();
+            members.insert(&p.name, dependencies);
+        }
+    }
+    groups
+}
+
+/// Orders a group's members into levels, each depending only on earlier
+/// ones, or returns a cycle as `a -> b -> a`.
+fn levels(members: &BTreeMap<&str, BTreeSet<&str>>) -> Result<Vec<Vec<String>>, String> {
+    let mut remaining = members.clone();
+    let mut levels = Vec::new();
+    while !remaining.is_empty() {
+        let ready: Vec<&str> = remaining.iter()
+            .filter(|(_, dependencies)| dependencies.iter().all(|d| !remaining.contains_key(d)))
+            .map(|(name, _)| *name)
+            .collect();
+        if ready.is_empty() {
+            return Err(find_cycle(&remaining));
+        }
+        for name in &ready {
+            remaining.remove(name);
+        }
+        levels.push(ready.into_iter().map(|name| name.to_string()).collect());
+    }
+    Ok(levels)
+}
+
+/// Every process left over by `levels` is on a cycle or depends on one, so
+/// following dependencies from any of them must come back to a process
+/// already visited.
+fn find_cycle(remaining: &BTreeMap<&str, BTreeSet<&str>>) -> String {
+    let mut path: Vec<&str> = vec![remaining.keys().next().unwrap()];
+    loop {
+        let current = path[path.len() - 1];
+        let next = remaining[current].iter().find(|d| remaining.contains_key(*d)).unwrap();
+        if let Some(start) = path.iter().position(|p| p == next) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(next);
+            return cycle.join(" -> ");
+        }
+        path.push(next);
+    }
+}
+
+/// Rejects documents where any group's dependencies form a cycle.
+pub fn check_cycles(processes: &HashMap<String, Process>) -> Result<(), String> {
+    for (group, members) in graphs(processes) {
+        levels(&members).map_err(|cycle| format!("Dependency cycle in group {}: {}", group, cycle))?;
+    }
+    Ok(())
+}
+
+/// The steps that set `run` on every member of `group`: dependencies first
+/// when starting, dependents first when stopping.
+pub fn plan(processes: &HashMap<String, Process>, group: &str, run: bool) -> Result<Vec<Vec<String>>, ControlApiError> {
+    let graphs = graphs(processes);
+    let members = graphs.get(group).ok_or_else(|| ControlApiError::GroupNotFound(group.to_string()))?;
+    let mut steps = levels(members)
+        .map_err(|cycle| ControlApiError::Validation(format!("Dependency cycle in group {}: {}", group, cycle)))?;
+    if !run {
+        steps.reverse();
+    }
+    Ok(steps)
+}
+
+/// The steps `control_group` would take, without changing anything.
+pub async fn preview_group(cache: &Arc<Mutex<MyCache>>, group: &str, run: bool) -> Result<GroupReport, ControlApiError> {
+    let mut cache = cache.lock().await;
+    // A preview saves nothing, so the cached processes will do when the store can't be read.
+    if let Err(e) = cache.refresh_cache(true).await {
+        error!("Error reading cache: {:?}", e);
+    }
+    Ok(GroupReport::planned(group, run, plan(&cache.all_processes, group, run)?))
+}
+
+/// Sets `run` on the members of `group` one step at a time, each its own
+/// write, pausing `interval` between steps without holding the cache lock.
+///
+/// Fails outright when the first step can't be applied; a later failure
+/// leaves the earlier steps applied and is reported in the result.
+pub async fn control_group(
+    cache: &Arc<Mutex<MyCache>>,
+    group: &str,
+    run: bool,
+    reason: &Option<String>,
+    interval: Duration,
+    ctx: &RequestContext,
+) -> Result<GroupReport, ControlApiError> {
+    let steps = {
+        let mut cache = cache.lock().await;
+        cache.refresh_cache(true).await?;
+        plan(&cache.all_processes, group, run)?
+    };
+    let mut report = GroupReport::planned(group, run, steps);
+    for i in 0..report.steps.len() {
+        if i > 0 && !interval.is_zero() {
+            tokio::time::sleep(interval).await;
+        }
+        let mut cache = cache.lock().await;
+        match cache.control_named(&report.steps[i].processes, run, reason, ctx).await {
+            Ok(()) => report.steps[i].status = StepStatus::Applied,
+            Err(e) if i == 0 => return Err(e),
+            Err(e) => {
+                report.steps[i].status = StepStatus::Failed;
+                report.steps[i].error = Some(e.to_string());
+                for step in &mut report.steps[i + 1..] {
+                    step.status = StepStatus::Skipped;
+                }
+                return Ok(report);
+            },
+        }
+    }
+    report.complete = true;
+    Ok(report)
+}
+
+#[test]
+fn test_plan_orders_and_rejects_cycles() {
+    let member = |name: &str, depends_on: &[&str]| {
+        let mut p = crate::cache::create_process(name, true, None);
+        p.group = Some("loaders".to_string());
+        p.depends_on = Some(depends_on.iter().map(|d| d.to_string()).collect()).filter(|d: &Vec<String>| !d.is_empty());
+        (name.to_string(), p)
+    };
+    let mut processes: HashMap<String, Process> = [
+        member("loader", &[]),
+        member("delete-table", &["loader"]),
+        member("indexer", &["loader", "outside-the-group"]),
+        member("report", &["indexer", "delete-table"]),
+    ].into_iter().collect();
+
+    let stop = plan(&processes, "loaders", false).unwrap();
+    assert_eq!(stop, vec![vec!["report"], vec!["delete-table", "indexer"], vec!["loader"]]);
+    assert!(matches!(plan(&processes, "other", true), Err(ControlApiError::GroupNotFound(_))));
+
+    processes.extend([member("loader", &["report"])]);
+    assert_eq!(check_cycles(&processes).unwrap_err(), "Dependency cycle in group loaders: delete-table -> loader -> report -> delete-table");
+}
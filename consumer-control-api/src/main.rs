@@ -1,240 +1,475 @@
-use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result, Error, patch};
-use aws_config::retry::ProvideErrorKind;
-use serde::{Deserialize, Deserializer, Serialize};
-use serde::de::{self, Visitor, MapAccess};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Result, Error, patch};
+use serde::{Deserialize, Serialize};
 use serde_qs as qs;
+use utoipa::{IntoParams, ToSchema};
 use clap::Parser;
-use std::{env, fmt};
-use std::path::PathBuf;
+use std::time::Duration;
+use std::io::Write;
+use std::collections::BTreeMap;
+use actix_web::http::header;
+use actix_web::dev::Service;
+use futures::future::FutureExt;
 
 mod cache;
 pub use cache::Process;
 pub use cache::MyCache;
-pub use cache::read_process;
+pub use consumer_control_client::model::{ProcessInput, ProcessPatchInput, ProcessQuery, ProcessQueryParams, ProcessMessage, ProcessRename};
+use consumer_control_client::model::{BulkTagsInput, ProcessTagsInput};
+use consumer_control_client::validation::{self, FieldError};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use actix_files as fs;
-use log::{debug, error, log_enabled, info, Level};
+use log::{error, info};
 
 mod s3_util;
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(short, long, default_value = "3000")]
-    port: String,
-}
-
-#[derive(Deserialize)]
+mod store;
+mod error;
+mod audit;
+mod auth;
+mod watch;
+mod metrics;
+mod query;
+mod namespace;
+mod config;
+mod groups;
+mod tags;
+mod openapi;
+use error::ControlApiError;
+use audit::RequestContext;
+use namespace::{Namespace, Namespaces};
+use config::{Args, Config, LogFormat};
+use openapi::{BadRequest, Conflict, Forbidden, NotFound, StorageUnavailable, Unauthorized, ValidationFailed, VersionNotFound};
+use audit::ProcessChange;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct QueryParams {
     process_name: String,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Answers for a process that was never registered, which consumers run.
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ErrorResponse {
     name: String,
     run: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GenericErrorResponse {
-    code: u32,
-    message: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct ProcessInput {
-    name: String,
-    run: bool,
-    tags: Option<Vec<String>>
-}
-
-#[derive(Serialize, Deserialize)]
-struct ProcessPatchInput {
-    name: String,
-    run: Option<bool>,
-    tags: Option<Vec<String>>
+/// Turns the rejected fields of a shared model's validation into a 422.
+fn validated(result: Result<(), Vec<FieldError>>) -> Result<(), ControlApiError> {
+    result.map_err(ControlApiError::InvalidFields)
 }
 
-impl ProcessPatchInput {
-    fn validatePatch(&self) -> bool {
-        // Check that either `run` or `tags` is provided
-        self.run.is_some() || self.tags.is_some()
-    }
-}
-
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DeleteProcessInput {
     process_name: String,
 }
 
-#[derive(Debug, Default)]
-pub struct ProcessQueryParams {
-    tags: Option<Vec<String>>,
-    name_patterns: Option<Vec<String>>,
-    run: Option<bool>,
-}
-
-impl<'de> Deserialize<'de> for ProcessQueryParams {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
-        enum Field { Tags, NamePrefixes, Run }
-
-        struct ProcessQueryParamsVisitor;
-
-        impl<'de> Visitor<'de> for ProcessQueryParamsVisitor {
-            type Value = ProcessQueryParams;
-
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("struct ProcessQueryParams")
-            }
-
-            fn visit_map<V>(self, mut map: V) -> Result<ProcessQueryParams, V::Error>
-            where
-                V: MapAccess<'de>,
-            {
-                let mut params = ProcessQueryParams::default();
-                while let Some(key) = map.next_key::<String>()? {
-                    match key.as_str() {
-                        "tags" => {
-                            if params.tags.is_some() {
-                                return Err(de::Error::duplicate_field("tags[]"));
-                            }
-                            params.tags = Some(map.next_value()?);
-                        },
-                        "name_patterns" => {
-                            if params.name_patterns.is_some() {
-                                return Err(de::Error::duplicate_field("name_patterns[]"));
-                            }
-                            params.name_patterns = Some(map.next_value()?);
-                        },
-                        "run" => {
-                            if params.run.is_some() {
-                                return Err(de::Error::duplicate_field("run"));
-                            }
-                            params.run = Some(map.next_value()?);
-                        },
-                        _ => return Err(de::Error::unknown_field(&key, FIELDS)),
-                    }
-                }
-                Ok(params)
-            }
-        }
-
-        const FIELDS: &'static [&'static str] = &["tags", "name_patterns", "run"];
-        deserializer.deserialize_struct("ProcessQueryParams", FIELDS, ProcessQueryParamsVisitor)
+/// Every condition in `params`, combined with AND.
+fn list_filter(params: &ProcessQueryParams) -> Result<query::Filter, ControlApiError> {
+    let mut filters = Vec::new();
+    if let Some(tags) = &params.tags {
+        filters.push(query::Filter::all_tags(tags));
+    }
+    if let Some(tags) = &params.any_tags {
+        filters.push(query::Filter::any_tag(tags));
+    }
+    if let Some(patterns) = &params.name_patterns {
+        filters.push(query::Filter::name_patterns(patterns).map_err(ControlApiError::BadRequest)?);
     }
+    if let Some(run) = params.run {
+        filters.push(query::Filter::Run(run));
+    }
+    if let Some(owner) = &params.owner {
+        filters.push(query::Filter::Text(query::TextField::Owner, owner.clone()));
+    }
+    if let Some(team) = &params.team {
+        filters.push(query::Filter::Text(query::TextField::Team, team.clone()));
+    }
+    for label in params.labels.iter().flatten() {
+        filters.push(query::parse_label(label));
+    }
+    if let Some(expr) = &params.filter {
+        filters.push(query::Filter::parse(expr).map_err(|e| ControlApiError::BadRequest(format!("Invalid filter: {}", e)))?);
+    }
+    Ok(query::Filter::And(filters))
 }
 
-#[derive(Deserialize)]
-pub struct ProcessQuery {
-    name_patterns: Option<Vec<String>>,
-    tags: Option<Vec<String>>,
+fn list_options(params: &ProcessQueryParams) -> Result<query::ListOptions, ControlApiError> {
+    query::ListOptions::new(params.sort.as_deref(), params.limit, params.cursor.clone(), params.fields.as_deref())
+        .map_err(ControlApiError::BadRequest)
 }
 
-#[derive(Serialize)]
-struct ProcessError {
-    name: String,
-    error_message: String,
+/// Processes carrying all of `tags` whose name matches any of `name_patterns`.
+fn selection_filter(selection: &ProcessQuery) -> Result<query::Filter, ControlApiError> {
+    let mut filters = Vec::new();
+    if let Some(tags) = &selection.tags {
+        filters.push(query::Filter::all_tags(tags));
+    }
+    if let Some(patterns) = &selection.name_patterns {
+        filters.push(query::Filter::name_patterns(patterns).map_err(ControlApiError::BadRequest)?);
+    }
+    Ok(query::Filter::And(filters))
 }
 
-#[derive(Serialize)]
-struct ProcessMessage {
-    name: String,
-    action: String,
+/// `?dry_run=true` reports what a bulk change would do without saving it.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DryRunQuery {
+    /// Report what would change without saving anything.
+    #[serde(default)]
+    dry_run: bool,
 }
 
-async fn get_json_value(data: web::Data<Arc<Mutex<MyCache>>>, query: web::Query<QueryParams>) -> impl Responder {
-    let mut data = data.lock().await;
-    data.refresh_cache(false).await;
-    match data.get_process(&query.process_name) {
-        Some(p) => HttpResponse::Ok().json(p),
+#[utoipa::path(
+    get, path = "/process", tag = "Single Process", operation_id = "getConsumer",
+    summary = "Get consumer status",
+    description = "Get all details of a consumer by name.  \n\
+        If a process that has never been registered with this API before is queried, only the name and run \
+        is returned with the run value set to true.  \n\
+        For a process with a schedule, run is the value the schedule gives for the current time.",
+    params(QueryParams),
+    responses(
+        (status = 200, description = "The details of a process", body = Process),
+        (status = 404, description = "The process was never registered", body = ErrorResponse,
+            example = json!({ "name": "ProcessX", "run": true })),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn get_json_value(ns: Namespace, query: web::Query<QueryParams>) -> Result<HttpResponse, ControlApiError> {
+    match ns.view.get_process(&query.process_name)? {
+        Some(p) => Ok(HttpResponse::Ok().json(p.resolved(chrono::Utc::now()))),
         None => {
             let error_response = ErrorResponse {
                 name: query.process_name.clone(),
                 run: true,
             };
-            HttpResponse::NotFound().json(error_response)
+            Ok(HttpResponse::NotFound().json(error_response))
         }
     }
 }
 
-async fn add_process_endpoint(data: web::Json<ProcessInput>, state: web::Data<Arc<Mutex<MyCache>>>) -> impl Responder {
-    let mut state = state.lock().await;
-    let process = data.into_inner();
-    let process_new = cache::create_process(&process.name, process.run, process.tags.clone());
-    match state.add_process(process_new.clone()).await {
-        Ok(_) => {
-            HttpResponse::Created().json(process_new)
-        }
-        Err(e) => {
-            let error_response = GenericErrorResponse { 
-                code: 500, 
-                message: format!("Failed to add process: {}", e)
-            };
-            HttpResponse::InternalServerError().json(error_response)
-        }
-    }
+#[utoipa::path(
+    post, path = "/process", tag = "Single Process", operation_id = "addConsumer",
+    summary = "Register a new process",
+    request_body(content = ProcessInput, example = json!({ "name": "Process1", "run": true, "tags": ["DMI", "V4"] })),
+    responses(
+        (status = 201, description = "Process registered successfully", body = Process),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn add_process_endpoint(data: web::Json<ProcessInput>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut process = data.into_inner();
+    process.normalize();
+    validated(process.validate_new())?;
+    let mut state = ns.cache.lock().await;
+    let process_new = process.to_process();
+    state.add_process(process_new.clone(), &ctx).await
+        .map_err(|e| e.with_current(state.get_process(&process_new.name).into_iter().collect()))?;
+    Ok(HttpResponse::Created().json(process_new))
 }
 
-async fn update_process_endpoint(data: web::Json<ProcessInput>, state: web::Data<Arc<Mutex<MyCache>>>) -> HttpResponse {
-    let mut state = state.lock().await;
-    let process = data.into_inner();
-    let process_new = cache::create_process(&process.name, process.run, process.tags.clone());
-    match state.modify_process(process_new.clone()).await {
-        Ok(_) => {
-            HttpResponse::Accepted().json("Process updated successfully!")
-        }
-        Err(e) => {
-            let error_response = GenericErrorResponse { 
-                code: 500, 
-                message: format!("Failed to add process: {}", e)
-            };
-            HttpResponse::InternalServerError().json(error_response)
-        }
-    }
+#[utoipa::path(
+    put, path = "/process", tag = "Single Process", operation_id = "updateConsumer",
+    summary = "Update an existing process",
+    request_body(content = ProcessInput, example = json!({ "name": "processf", "run": true, "tags": ["DMI", "V4"] })),
+    responses(
+        (status = 202, description = "Process updated successfully", body = String),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, response = NotFound),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn update_process_endpoint(data: web::Json<ProcessInput>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut process = data.into_inner();
+    process.normalize();
+    validated(process.validate())?;
+    let mut state = ns.cache.lock().await;
+    let process_new = process.to_process();
+    state.modify_process(process_new.clone(), &ctx).await
+        .map_err(|e| e.with_current(state.get_process(&process_new.name).into_iter().collect()))?;
+    Ok(HttpResponse::Accepted().json("Process updated successfully!"))
 }
 
-async fn delete_process_endpoint(query: web::Query<DeleteProcessInput>, state: web::Data<Arc<Mutex<MyCache>>>) -> impl Responder {
-    let mut state = state.lock().await;
+#[utoipa::path(
+    delete, path = "/process", tag = "Single Process", operation_id = "deleteConsumer",
+    summary = "Delete an existing process",
+    params(DeleteProcessInput),
+    responses(
+        (status = 200, description = "Process deleted successfully", body = String),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, response = NotFound),
+        (status = 409, response = Conflict),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn delete_process_endpoint(query: web::Query<DeleteProcessInput>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut state = ns.cache.lock().await;
     let process_name = &query.process_name;
     info!("Deleting process: {}", process_name);
-    match state.delete_process(&process_name).await {
-        Ok(_) => {
-            HttpResponse::Ok().json(format!("Process {} deleted successfully", process_name))
-        }
-        Err(e) => {
-            let error_response = GenericErrorResponse { 
-                code: 500, 
-                message: format!("Failed to delete process: {}", e)
-            };
-            HttpResponse::InternalServerError().json(error_response)
-        }
-    }
+    state.delete_process(process_name, &ctx).await
+        .map_err(|e| e.with_current(state.get_process(process_name).into_iter().collect()))?;
+    Ok(HttpResponse::Ok().json(format!("Process {} deleted successfully", process_name)))
 }
 
-async fn patch_process_endpoint(input: web::Json<ProcessPatchInput>, state: web::Data<Arc<Mutex<MyCache>>>) -> impl Responder {
-    let mut state = state.lock().await;
-    if !input.validatePatch() {
-        return HttpResponse::InternalServerError().json("Either 'run' or 'tags' must be specified.");
+#[utoipa::path(
+    patch, path = "/process", tag = "Single Process", operation_id = "patchConsumer",
+    summary = "Update an existing process (partial)",
+    description = "Only the fields given are changed; at least one besides `name` must be specified.",
+    request_body(content = ProcessPatchInput, example = json!({ "name": "processx", "run": true, "tags": ["Postgresql", "Analytics"] })),
+    responses(
+        (status = 200, description = "Process patched successfully", body = String),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, response = NotFound),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn patch_process_endpoint(input: web::Json<ProcessPatchInput>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut input = input.into_inner();
+    input.normalize();
+    if !input.has_changes() {
+        return Err(ControlApiError::Validation("Either 'run', 'tags', 'schedule', 'group', 'depends_on' or a metadata field must be specified.".to_string()));
     }
+    validated(input.validate())?;
+    let mut state = ns.cache.lock().await;
     info!("Patching process: {}", input.name);
-    match state.update_process_partial(&input.name, input.run, input.tags.clone()).await {
-        Ok(_) => {
-            HttpResponse::Ok().json("Process patched successfully.")
-        }
-        Err(e) => {
-            let error_response = GenericErrorResponse { 
-                code: 500, 
-                message: format!("Failed to patch process: {}", e)
-            };
-            HttpResponse::InternalServerError().json(error_response)
-        }
+    state.update_process_partial(&input, &ctx).await
+        .map_err(|e| e.with_current(state.get_process(&input.name).into_iter().collect()))?;
+    Ok(HttpResponse::Ok().json("Process patched successfully."))
+}
+
+#[utoipa::path(
+    post, path = "/process/rename", tag = "Single Process", operation_id = "renameConsumer",
+    summary = "Rename a process",
+    description = "Moves a process to a new name in one write. It keeps its run state, tags and schedule, its history \
+        follows it, and processes that depended on it depend on the new name.",
+    request_body(content = ProcessRename, example = json!({ "name": "etl-load", "new_name": "etl-ingest" })),
+    responses(
+        (status = 200, description = "The process under its new name", body = Process),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, response = NotFound),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn rename_process_endpoint(input: web::Json<ProcessRename>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let input = input.into_inner();
+    validated(input.validate())?;
+    let mut state = ns.cache.lock().await;
+    info!("Renaming process {} to {}", input.name, input.new_name);
+    let process = state.rename_process(&input.name, &input.new_name, &ctx).await
+        .map_err(|e| e.with_current([&input.name, &input.new_name].into_iter().filter_map(|name| state.get_process(name)).collect()))?;
+    Ok(HttpResponse::Ok().json(process))
+}
+
+#[utoipa::path(
+    patch, path = "/process/tags", tag = "Tags", operation_id = "changeConsumerTags",
+    summary = "Add and remove tags of a process",
+    description = "Takes the tags in `remove` off the process and puts those in `add` on it, leaving its other tags \
+        alone.",
+    request_body(content = ProcessTagsInput, example = json!({ "name": "process1", "add": ["v4"], "remove": ["v3"] })),
+    responses(
+        (status = 200, description = "The process with its new tags", body = Process),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, response = NotFound),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn change_process_tags(input: web::Json<ProcessTagsInput>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut input = input.into_inner();
+    input.normalize();
+    validated(input.validate())?;
+    let (add, remove) = (input.add.unwrap_or_default(), input.remove.unwrap_or_default());
+    let mut state = ns.cache.lock().await;
+    let process = state.change_process_tags(&input.name, &add, &remove, &ctx).await
+        .map_err(|e| e.with_current(state.get_process(&input.name).into_iter().collect()))?;
+    Ok(HttpResponse::Ok().json(process))
+}
+
+#[utoipa::path(
+    patch, path = "/processes/tags", tag = "Tags", operation_id = "changeTags",
+    summary = "Add and remove tags of every process selected",
+    description = "Takes the tags in `remove` off and puts those in `add` on every process carrying all of `tags` \
+        whose name matches any of `name_patterns`, in one write.  \n\
+        With `dry_run=true`, nothing is saved and the response lists the processes that would change, before and \
+        after.",
+    params(DryRunQuery),
+    request_body(content = BulkTagsInput, example = json!({ "name_patterns": ["etl-*"], "add": ["nightly"], "remove": ["hourly"] })),
+    responses(
+        (status = 200, description = "The processes selected, with their new tags", body = BulkResult),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn change_tags(input: web::Json<BulkTagsInput>, options: web::Query<DryRunQuery>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut input = input.into_inner();
+    input.normalize();
+    validated(input.validate())?;
+    let filter = selection_filter(&input.selection())?;
+    let (add, remove) = (input.add.unwrap_or_default(), input.remove.unwrap_or_default());
+    let mut state = ns.cache.lock().await;
+    if options.dry_run {
+        return Ok(HttpResponse::Ok().json(BulkResult::DryRun(state.preview_change_tags(&filter, &add, &remove).await?)));
     }
+    let processes = state.change_tags(&filter, &add, &remove, &ctx).await
+        .map_err(|e| e.with_current(state.filter_processes(&filter)))?;
+    Ok(HttpResponse::Ok().json(BulkResult::Applied(processes)))
+}
+
+#[utoipa::path(
+    get, path = "/tags", tag = "Tags", operation_id = "listTags",
+    summary = "List every tag in use",
+    description = "Every tag carried by a process, by tag, with the number of processes carrying it and how many of \
+        them run and are stopped, as their schedules give it for the current time.",
+    responses(
+        (status = 200, description = "The tags in use", body = Vec<tags::TagSummary>,
+            example = json!([{ "tag": "dmi", "count": 3, "running": 2, "stopped": 1 }])),
+        (status = 401, response = Unauthorized),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn list_tags(ns: Namespace) -> Result<HttpResponse, ControlApiError> {
+    let processes = ns.view.filter_processes(&query::Filter::And(Vec::new()), chrono::Utc::now())?;
+    Ok(HttpResponse::Ok().json(tags::summarize(&processes)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    process_name: String,
+    /// First UTC day to include; reading a shorter range of a long trail is faster.
+    since: Option<chrono::NaiveDate>,
+    /// Last UTC day to include.
+    until: Option<chrono::NaiveDate>,
+}
+
+#[utoipa::path(
+    get, path = "/process/history", tag = "Single Process", operation_id = "getConsumerHistory",
+    summary = "Get the change history of a process",
+    description = "Every recorded change to a process, oldest first, including the changes made before it was deleted \
+        and those recorded under the names it had before it was renamed.",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "The audit entries of the process", body = Vec<audit::AuditEntry>),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn get_process_history(query: web::Query<HistoryQuery>, ns: Namespace) -> Result<HttpResponse, ControlApiError> {
+    // Reading the trail can take a while, so don't hold the cache lock for it.
+    let store = ns.cache.lock().await.store.clone();
+    let days = store::DayRange { since: query.since, until: query.until };
+    let history = audit::history(store.as_ref(), &query.process_name, &days).await?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct VersionDiffQuery {
+    from: String,
+    /// Defaults to the processes currently served.
+    to: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RollbackQuery {
+    /// A `version_id` from `/processes/versions`.
+    version: String,
+}
+
+#[utoipa::path(
+    get, path = "/processes/versions", tag = "Versions", operation_id = "listVersions",
+    summary = "List the stored versions of the processes",
+    description = "Every retained version of the process document, newest first. Every save is retained: S3 keeps \
+        object versions when versioning is enabled on the bucket, and the local backend keeps timestamped copies \
+        under `versions/<document name>/` next to the document.",
+    responses(
+        (status = 200, description = "The retained versions", body = Vec<store::DocumentVersion>),
+        (status = 401, response = Unauthorized),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn get_process_versions(ns: Namespace) -> Result<HttpResponse, ControlApiError> {
+    let store = ns.cache.lock().await.store.clone();
+    let versions = store.list_versions().await?;
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+#[utoipa::path(
+    get, path = "/processes/diff", tag = "Versions", operation_id = "diffVersions",
+    summary = "Compare two versions of the processes",
+    description = "Every process that differs between version `from` and version `to`, by name. Without `to`, \
+        compares with the processes currently served.",
+    params(VersionDiffQuery),
+    responses(
+        (status = 200, description = "The processes that differ", body = Vec<ProcessChange>),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 404, response = VersionNotFound),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn diff_process_versions(query: web::Query<VersionDiffQuery>, ns: Namespace) -> Result<HttpResponse, ControlApiError> {
+    let store = ns.cache.lock().await.store.clone();
+    let from = store.load_version(&query.from).await?;
+    let to = match &query.to {
+        Some(version_id) => store.load_version(version_id).await?,
+        None => (*ns.view.load()?).clone(),
+    };
+    Ok(HttpResponse::Ok().json(audit::diff(&from, &to)))
+}
+
+#[utoipa::path(
+    post, path = "/processes/rollback", tag = "Versions", operation_id = "rollbackVersion",
+    summary = "Restore an earlier version of the processes",
+    description = "Replaces the stored processes with those saved in `version`, with the same conditional write and \
+        audit trail as any other change. Processes whose run state is restored take effect now, ahead of any \
+        scheduled transition that is already due.",
+    params(RollbackQuery),
+    responses(
+        (status = 200, description = "What the rollback changed", body = Vec<ProcessChange>),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, response = VersionNotFound),
+        (status = 409, response = Conflict),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn rollback_processes(query: web::Query<RollbackQuery>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut state = ns.cache.lock().await;
+    info!("Rolling processes back to version {}", query.version);
+    let changes = state.rollback(&query.version, &ctx).await?;
+    Ok(HttpResponse::Ok().json(changes))
 }
 
 /*
@@ -244,131 +479,525 @@ async fn get_processes(query: web::Query<ProcessQueryParams>, state: web::Data<A
     HttpResponse::Ok().json(processes)
 }
 */
+/// Set on a page of `GET /processes` when more processes follow; pass it
+/// back as `cursor` to get them.
+const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
 fn decode_brackets(encoded_str: &str) -> String {
-    let decoded = encoded_str
+    encoded_str
         .replace("%5B", "[")
-        .replace("%5D", "]");
-    decoded
+        .replace("%5D", "]")
 }
 
-async fn get_processes(req: HttpRequest, state: web::Data<Arc<Mutex<MyCache>>>) -> HttpResponse {
+#[utoipa::path(
+    get, path = "/processes", tag = "Complex", operation_id = "getConsumers",
+    summary = "Query processes by multiple parameters",
+    description = "Every condition given must hold. Results are sorted by name unless `sort` says otherwise. With \
+        `limit`, the `X-Next-Cursor` response header is set when more processes follow; pass it back as `cursor` \
+        with the same `sort` to get the next page. With `fields`, each process only carries the fields named.",
+    params(ProcessQueryParams),
+    responses(
+        (status = 200, description = "The matching processes", body = Vec<Process>,
+            headers(("X-Next-Cursor" = String, description = "Set when more processes follow; pass it back as `cursor`"))),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn get_processes(req: HttpRequest, ns: Namespace) -> Result<HttpResponse, ControlApiError> {
     let query_string = req.query_string();
     let query_string_decoded = decode_brackets(query_string);
-    let query_params: Result<ProcessQueryParams, _> = qs::from_str(&query_string_decoded);
-
-    match query_params {
-        Ok(params) => {
-            let state = state.lock().await;
-            let mut processes = state.filter_processes(&params).clone();
-            processes.sort_by_key(|p| p.name.clone());
-            HttpResponse::Ok().json(processes)
-        },
-        Err(_) => HttpResponse::BadRequest().body("Invalid query parameters"),
+    let params: ProcessQueryParams = qs::from_str(&query_string_decoded)
+        .map_err(|e| ControlApiError::BadRequest(format!("Invalid query parameters: {}", e)))?;
+
+    let filter = list_filter(&params)?;
+    let options = list_options(&params)?;
+    let processes = ns.view.filter_processes(&filter, chrono::Utc::now())?;
+    let (page, next_cursor) = options.apply(processes).map_err(ControlApiError::BadRequest)?;
+    let mut response = HttpResponse::Ok();
+    if let Some(cursor) = next_cursor {
+        response.insert_header((NEXT_CURSOR_HEADER, cursor));
+    }
+    Ok(response.json(page))
+}
+
+#[utoipa::path(
+    get, path = "/process/watch", tag = "Complex", operation_id = "watchConsumers",
+    summary = "Wait for processes to start or stop",
+    description = "Returns the run state of the matching processes as soon as it differs from `version`, so \
+        consumers can react to a stop without polling.  \n\
+        Long-polling: pass the `version` from the previous response; when nothing changes within `timeout` \
+        seconds, 304 is returned and the request should be repeated.  \n\
+        Server-Sent Events: with `Accept: text/event-stream` the connection stays open and a `processes` event \
+        is sent on every change, with the version as the event ID. A reconnecting client may send it back in \
+        `Last-Event-ID`.",
+    params(watch::WatchParams),
+    responses(
+        (status = 200, description = "The current run state of the matching processes", content(
+            (watch::WatchSnapshot = "application/json"),
+            (String = "text/event-stream", example = "event: processes\nid: 0175eb1f44bab713\ndata: {\"version\":\"0175eb1f44bab713\",\"processes\":[{\"name\":\"process1\",\"run\":false}]}\n"),
+        )),
+        (status = 304, description = "Nothing changed before the timeout"),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+/// Long-polls until the run state of the matching processes differs from
+/// `version`, or streams every change as Server-Sent Events when the client
+/// accepts `text/event-stream`.
+async fn watch_processes(req: HttpRequest, ns: Namespace) -> Result<HttpResponse, ControlApiError> {
+    let query_string_decoded = decode_brackets(req.query_string());
+    let params: watch::WatchParams = qs::from_str(&query_string_decoded)
+        .map_err(|e| ControlApiError::BadRequest(format!("Invalid query parameters: {}", e)))?;
+    let filter = watch::filter(&params)?;
+    let view = ns.view;
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    if header(header::ACCEPT).is_some_and(|accept| accept.contains("text/event-stream")) {
+        // Clients reconnecting after a dropped stream send the last event ID they saw.
+        let version = params.version.clone().or_else(|| header(header::HeaderName::from_static("last-event-id")).map(|v| v.to_string()));
+        return Ok(HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(watch::event_stream(view, filter, version)));
     }
+
+    let wait = Duration::from_secs(params.timeout.unwrap_or(watch::DEFAULT_WAIT_SECS).min(watch::MAX_WAIT_SECS));
+    match watch::wait_for_change(view, filter, params.version.clone(), wait).await? {
+        Some(snapshot) => Ok(HttpResponse::Ok().json(snapshot)),
+        None => Ok(HttpResponse::NotModified().finish()),
+    }
+}
+
+/// By name, since routes under `/ns/{namespace}` carry a second segment.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+struct ActionPath {
+    /// `start` or `stop`.
+    action: String,
+}
+
+/// `PATCH /processes/{action}` and `DELETE /processes` answer with the
+/// processes changed or deleted, or with `dry_run=true` with what would change.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum BulkResult {
+    Applied(Vec<Process>),
+    DryRun(Vec<ProcessChange>),
 }
 
+#[utoipa::path(
+    patch, path = "/processes/{action}", tag = "Complex", operation_id = "startStopConsumers",
+    summary = "Start or stop processes (consumers)",
+    description = "Start or stop the processes carrying all of `tags` whose name matches any of `name_patterns`.  \n\
+        With `dry_run=true`, nothing is saved and the response lists the processes that would be started or \
+        stopped, before and after.",
+    params(ActionPath, DryRunQuery),
+    request_body(content = ProcessQuery, example = json!({ "name_patterns": ["process*"], "tags": ["v4", "dmi"] })),
+    responses(
+        (status = 200, description = "Processes started / stopped successfully", body = BulkResult),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
 #[patch("/processes/{action}")]
 async fn start_stop_consumers(
-    path: web::Path<String>, // Extracts the 'action' path parameter
+    path: web::Path<ActionPath>, // Extracts the 'action' path parameter
     query: web::Json<ProcessQuery>, // Extracts and deserializes the JSON request body
-    state: web::Data<Arc<Mutex<MyCache>>>
-) -> impl Responder {
-    let action = path.into_inner();
+    options: web::Query<DryRunQuery>,
+    ns: Namespace,
+    ctx: RequestContext,
+) -> Result<HttpResponse, ControlApiError> {
+    let action = path.into_inner().action;
     let query = query.into_inner();
-    
-    match cache::run_str_to_bool(&action) {
-        Ok(run) => {
-            let mut state = state.lock().await;
-            match state.control_processes(&query, run).await {
-                Ok(processes) => {
-                    let mut processes = processes.clone();
-                    processes.sort_by_key(|p| p.name.clone());
-                    HttpResponse::Ok().json(processes)
-                }
-                Err(e) => {
-                    let error_response = GenericErrorResponse { 
-                        code: 500, 
-                        message: format!("Failed to start/stop processes: {}", e)
-                    };
-                    HttpResponse::InternalServerError().json(error_response)
-                }
-            }
-        },
-        Err(msg) => {
-            HttpResponse::BadRequest().body(msg)
-        }
+    let run = cache::run_str_to_bool(&action).map_err(ControlApiError::BadRequest)?;
+    validated(query.validate())?;
+    let filter = selection_filter(&query)?;
+
+    let mut state = ns.cache.lock().await;
+    if options.dry_run {
+        return Ok(HttpResponse::Ok().json(BulkResult::DryRun(state.preview_control_processes(&filter, run, &query.reason).await)));
     }
+    let mut processes = state.control_processes(&filter, run, &query.reason, &ctx).await
+        .map_err(|e| e.with_current(state.filter_processes(&filter)))?;
+    processes.sort_by_key(|p| p.name.clone());
+    Ok(HttpResponse::Ok().json(BulkResult::Applied(processes)))
 }
 
-async fn put_processes(process_inputs: web::Json<Vec<ProcessPatchInput>>, state: web::Data<Arc<Mutex<MyCache>>>) -> impl Responder {
-    let mut state = state.lock().await;
-    match state.merge_processes(process_inputs.into_inner()).await {
-        Ok(process_messages) => {
-            HttpResponse::Ok().json(process_messages)
-        }
-        Err(e) => {
-            let error_response = GenericErrorResponse { 
-                code: 500, 
-                message: format!("Failed to update processes: {}", e)
-            };
-            HttpResponse::InternalServerError().json(error_response)
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct BulkDeleteQuery {
+    /// Report what would be deleted without deleting anything.
+    #[serde(default)]
+    dry_run: bool,
+    /// How many processes the selection matches, as a dry run reports; required to delete.
+    confirm: Option<usize>,
+}
+
+#[utoipa::path(
+    delete, path = "/processes", tag = "Complex", operation_id = "deleteConsumers",
+    summary = "Delete processes by name patterns or tags",
+    description = "Deletes every process carrying all of `tags` whose name matches any of `name_patterns`, in one \
+        write. `confirm` must be the number of processes selected when the delete is applied, otherwise nothing \
+        is deleted and the response is 409 with the error `confirmation_mismatch`.  \n\
+        With `dry_run=true`, nothing is deleted and the response lists the processes that would be.",
+    params(BulkDeleteQuery),
+    request_body(content = ProcessQuery, example = json!({ "name_patterns": ["test-*"], "tags": ["qa"] })),
+    responses(
+        (status = 200, description = "The processes deleted", body = BulkResult),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn delete_processes_endpoint(
+    query: web::Json<ProcessQuery>,
+    options: web::Query<BulkDeleteQuery>,
+    ns: Namespace,
+    ctx: RequestContext,
+) -> Result<HttpResponse, ControlApiError> {
+    let query = query.into_inner();
+    validated(query.validate_selection())?;
+    let filter = selection_filter(&query)?;
+    let mut state = ns.cache.lock().await;
+    if options.dry_run {
+        return Ok(HttpResponse::Ok().json(BulkResult::DryRun(state.preview_delete_processes(&filter).await)));
+    }
+    let confirm = options.confirm.ok_or_else(|| ControlApiError::Validation(
+        "'confirm' must be set to the number of processes to delete, as reported with dry_run=true.".to_string()
+    ))?;
+    info!("Deleting {} processes", confirm);
+    let deleted = state.delete_processes(&filter, confirm, &ctx).await
+        .map_err(|e| e.with_current(state.filter_processes(&filter)))?;
+    Ok(HttpResponse::Ok().json(BulkResult::Applied(deleted)))
+}
+
+/// By name, since routes under `/ns/{namespace}` carry another segment.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+struct GroupPath {
+    #[param(example = "etl")]
+    group: String,
+    /// `start` or `stop`.
+    action: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GroupControlQuery {
+    /// Report the planned steps without saving anything.
+    #[serde(default)]
+    dry_run: bool,
+    /// Seconds to wait between steps, giving consumers time to react.
+    #[serde(default)]
+    #[param(maximum = 600)]
+    interval_secs: u64,
+    /// Recorded as the reason of every process started or stopped.
+    reason: Option<String>,
+}
+
+#[utoipa::path(
+    patch, path = "/groups/{group}/{action}", tag = "Groups", operation_id = "controlGroup",
+    summary = "Start or stop a dependency group in order",
+    description = "Sets `run` on every process whose `group` is `group`, one step at a time. Starting begins with \
+        the processes that depend on no other member; stopping begins with the processes no other member depends \
+        on. Each step is saved and audited on its own.  \n\
+        When a step after the first fails, the earlier steps stay applied and the response is 207 with the status \
+        of every step.",
+    params(GroupPath, GroupControlQuery),
+    responses(
+        (status = 200, description = "Every step was applied, or with `dry_run=true` the planned steps", body = groups::GroupReport),
+        (status = 207, description = "A step failed after earlier steps were applied", body = groups::GroupReport),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 404, description = "No process belongs to the group", body = error::GenericErrorResponse,
+            example = json!({ "code": 404, "error": "group_not_found", "message": "No process belongs to group etl" })),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+/// Starts or stops a dependency group in order. Answers 207 with the steps
+/// taken when a step after the first fails.
+async fn control_group(path: web::Path<GroupPath>, query: web::Query<GroupControlQuery>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let run = cache::run_str_to_bool(&path.action).map_err(ControlApiError::BadRequest)?;
+    if query.interval_secs > groups::MAX_INTERVAL_SECS {
+        return Err(ControlApiError::Validation(format!("interval_secs must be at most {}.", groups::MAX_INTERVAL_SECS)));
+    }
+    let mut errors = Vec::new();
+    validation::check_text(&validation::LONG_TEXT, "reason", &query.reason, &mut errors);
+    validated(validation::into_result(errors))?;
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(groups::preview_group(&ns.cache, &path.group, run).await?));
+    }
+    info!("{} group {}", if run { "Starting" } else { "Stopping" }, path.group);
+    let interval = Duration::from_secs(query.interval_secs);
+    let report = groups::control_group(&ns.cache, &path.group, run, &query.reason, interval, &ctx).await?;
+    if report.complete {
+        Ok(HttpResponse::Ok().json(report))
+    } else {
+        Ok(HttpResponse::MultiStatus().json(report))
+    }
+}
+
+/// `PUT /processes` answers with what was saved, or with `dry_run=true`
+/// with what would change.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum MergeResult {
+    Saved(Vec<ProcessMessage>),
+    DryRun(Vec<ProcessChange>),
+}
+
+#[utoipa::path(
+    put, path = "/processes", tag = "Complex", operation_id = "putConsumers",
+    summary = "Add/Update multiple processes",
+    description = "Processes that already exist are updated, the others are added; for an addition without `run`, \
+        it is taken as false.  \n\
+        With `dry_run=true`, nothing is saved and the response lists the processes that would be added or changed, \
+        before and after.",
+    params(DryRunQuery),
+    request_body(content = Vec<ProcessPatchInput>, example = json!([{ "name": "process1" }, { "name": "process2", "tags": ["es", "md"] }, { "name": "process3", "run": true }])),
+    responses(
+        (status = 200, description = "Processes saved successfully", body = MergeResult),
+        (status = 400, response = BadRequest),
+        (status = 401, response = Unauthorized),
+        (status = 403, response = Forbidden),
+        (status = 409, response = Conflict),
+        (status = 422, response = ValidationFailed),
+        (status = 503, response = StorageUnavailable),
+    ),
+)]
+async fn put_processes(process_inputs: web::Json<Vec<ProcessPatchInput>>, options: web::Query<DryRunQuery>, ns: Namespace, ctx: RequestContext) -> Result<HttpResponse, ControlApiError> {
+    let mut process_inputs = process_inputs.into_inner();
+    let mut errors = Vec::new();
+    let mut state = ns.cache.lock().await;
+    for (i, input) in process_inputs.iter_mut().enumerate() {
+        input.normalize();
+        // Only the names of the processes being added have to follow the naming rule.
+        let result = if state.get_process(&input.name).is_some() { input.validate() } else { input.validate_new() };
+        if let Err(fields) = result {
+            errors.extend(validation::at_index(i, fields));
         }
-    
     }
+    validated(validation::into_result(errors))?;
+    if options.dry_run {
+        return Ok(HttpResponse::Ok().json(MergeResult::DryRun(state.preview_merge_processes(&process_inputs).await)));
+    }
+    let names: Vec<String> = process_inputs.iter().map(|p| p.name.clone()).collect();
+    let process_messages = state.merge_processes(process_inputs, &ctx).await
+        .map_err(|e| e.with_current(names.iter().filter_map(|name| state.get_process(name)).collect()))?;
+    Ok(HttpResponse::Ok().json(MergeResult::Saved(process_messages)))
+}
+
+#[derive(Serialize, ToSchema)]
+struct Health {
+    /// `degraded` while any namespace serves a last-known-good snapshot.
+    #[schema(example = "ok")]
+    status: &'static str,
+}
+
+#[utoipa::path(
+    get, path = "/healthz", tag = "Operations", operation_id = "healthz",
+    summary = "Liveness probe",
+    responses((status = 200, description = "The service is running", body = Health)),
+)]
+async fn healthz(namespaces: web::Data<Namespaces>) -> HttpResponse {
+    let status = if namespaces.iter().any(|ns| ns.view.is_degraded()) { "degraded" } else { "ok" };
+    HttpResponse::Ok().json(Health { status })
+}
+
+#[derive(Serialize, ToSchema)]
+struct Readiness {
+    /// Age of the least recently refreshed namespace.
+    cache_age_seconds: u64,
+    /// True while any namespace serves a last-known-good snapshot.
+    degraded: bool,
+    namespaces: BTreeMap<String, NamespaceReadiness>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct NamespaceReadiness {
+    cache_age_seconds: u64,
+    etag: String,
+    /// True while serving a last-known-good snapshot.
+    degraded: bool,
+}
+
+#[utoipa::path(
+    get, path = "/readyz", tag = "Operations", operation_id = "readyz",
+    summary = "Readiness probe",
+    description = "Fails when the processes of any namespace have not been refreshed from storage within the \
+        server's readiness threshold.",
+    responses(
+        (status = 200, description = "The cached processes are fresh", body = Readiness),
+        (status = 503, description = "The cached processes are too old", body = Readiness),
+    ),
+)]
+/// Ready while the processes of every namespace have been refreshed from
+/// storage within `max_age_secs`.
+async fn readyz(namespaces: web::Data<Namespaces>, max_age_secs: u64) -> HttpResponse {
+    let age = namespaces.iter().map(|ns| ns.view.age_secs()).max().unwrap_or(0);
+    let details = namespaces.iter()
+        .map(|ns| (ns.name.clone(), NamespaceReadiness { cache_age_seconds: ns.view.age_secs(), etag: ns.view.etag(), degraded: ns.view.is_degraded() }))
+        .collect();
+    let degraded = namespaces.iter().any(|ns| ns.view.is_degraded());
+    let body = Readiness { cache_age_seconds: age, degraded, namespaces: details };
+    if age > max_age_secs {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+#[utoipa::path(
+    get, path = "/metrics", tag = "Operations", operation_id = "metrics",
+    summary = "Prometheus metrics",
+    description = "Request counts and latencies per route, refresh failures and write conflicts, and per namespace \
+        the cache age and ETag and the number of running and stopped processes, in the Prometheus text format.",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain")),
+)]
+async fn metrics_endpoint(namespaces: web::Data<Namespaces>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::METRICS.render(&namespaces))
+}
+
+/// Reports malformed JSON bodies and query strings in the same shape as every
+/// other error instead of actix's plain text default.
+fn bad_request_handler<E: std::fmt::Display>(err: E, _req: &HttpRequest) -> Error {
+    ControlApiError::BadRequest(err.to_string()).into()
+}
+
+/// The process routes, served for the default namespace at the root and for
+/// every namespace under `/ns/{namespace}`.
+fn process_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(
+            web::resource("/process")
+                .route(web::get().to(get_json_value))
+                .route(web::post().to(add_process_endpoint))
+                .route(web::put().to(update_process_endpoint))
+                .route(web::delete().to(delete_process_endpoint))
+                .route(web::patch().to(patch_process_endpoint)),
+        )
+        .route("/process/history", web::get().to(get_process_history))
+        .route("/process/rename", web::post().to(rename_process_endpoint))
+        .route("/process/tags", web::patch().to(change_process_tags))
+        .route("/process/watch", web::get().to(watch_processes))
+        .service(
+            web::resource("/processes")
+                .route(web::get().to(get_processes))
+                .route(web::put().to(put_processes))
+                .route(web::delete().to(delete_processes_endpoint)),
+        )
+        // Registered before `/processes/{action}`, which would otherwise claim these paths.
+        .route("/processes/versions", web::get().to(get_process_versions))
+        .route("/processes/diff", web::get().to(diff_process_versions))
+        .route("/processes/rollback", web::post().to(rollback_processes))
+        .route("/processes/tags", web::patch().to(change_tags))
+        .service(start_stop_consumers)
+        .route("/tags", web::get().to(list_tags))
+        .route("/groups/{group}/{action}", web::patch().to(control_group));
+}
+
+/// Every route but the docs, which are served from the configured directory.
+fn api_routes(cfg: &mut web::ServiceConfig, ready_max_age_secs: u64) {
+    cfg
+        .route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(move |view| readyz(view, ready_max_age_secs)))
+        .route("/metrics", web::get().to(metrics_endpoint))
+        .configure(process_routes)
+        .service(web::scope("/ns/{namespace}").configure(process_routes));
+}
+
+/// Logs as plain text, or as one JSON object per line for log shippers.
+/// Levels still come from `RUST_LOG`.
+fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
     let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config).expect("Failed to render configuration"));
+        return Ok(());
+    }
+    init_logger(config.server.log_format);
 
-    let server_str = format!("{}:{}", "0.0.0.0", args.port);
-
-    let client = s3_util::get_client().await.expect("Failed to get S3 client");
-    cache::S3_CLIENT.set(client).expect("Failed to set S3_CLIENT");
-    info!("Set up new S3 Client!");
+    let server_str = config.bind_address();
 
-    let (all_processes, etag) = cache::read_data().await.expect("Failed to read data");
-    let cache_time = cache::get_current_time();
-    let cached_data = MyCache {
-        all_processes,
-        cache_time,
-        etag
+    // Neither the store nor a snapshot could be read, so there is nothing to serve.
+    let namespaces = match Namespaces::open(&config.storage).await {
+        Ok(namespaces) => namespaces,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
     };
-    let cached_data = Arc::new(Mutex::new(cached_data));
+    let namespaces = web::Data::new(namespaces);
+
+    let authorizer = auth::Authorizer::from_files(config.auth.api_keys_file.as_deref(), config.auth.jwt_keys_file.as_deref(), config.auth.allow_anonymous_reads)
+        .expect("Failed to load authentication keys");
+    let authorizer = Arc::new(authorizer);
+
+    info!("Listening on {}", server_str);
+    let docs_dir = config.server.docs_dir.clone();
+    let opanapi_file = docs_dir.join("openapi.html");
 
-    info!("Using port: {}", args.port);
-    let docs_dir = env::var("DOCS_DIR").unwrap_or_else(|_| "./docs".to_string());
-    let opanapi_file = format!("{}/openapi.html", docs_dir);
+    let ready_max_age_secs = config.server.ready_max_age_secs;
 
     HttpServer::new(move || {
         let openapi_file_for_route = opanapi_file.clone();
 
         App::new()
-            .app_data(web::Data::new(cached_data.clone()))
+            .wrap_fn(|req, srv| srv.call(req).map(|res| res.map(audit::warn_if_unrecorded)))
+            .wrap(auth::Authentication::new(authorizer.clone()))
+            // Outermost, so rejected requests are counted too.
+            .wrap(metrics::RequestMetrics)
+            .app_data(namespaces.clone())
+            .app_data(web::JsonConfig::default().error_handler(bad_request_handler))
+            .app_data(web::QueryConfig::default().error_handler(bad_request_handler))
+            .configure(openapi::routes)
             .service(fs::Files::new("/docs", &docs_dir).show_files_listing())
             // .route("/", web::get().to(|| async { fs::NamedFile::open("./docs/openapi.html").unwrap() }))
             .route("/", web::get().to(move || {
-                let openapi_path = PathBuf::from(openapi_file_for_route.clone());
+                let openapi_path = openapi_file_for_route.clone();
                 async move {
                     fs::NamedFile::open(&openapi_path)
                         .map_err(Error::from) // This ensures the error is converted properly
                 }
             }))
-            .service(
-                web::resource("/process")
-                    .route(web::get().to(get_json_value))
-                    .route(web::post().to(add_process_endpoint))
-                    .route(web::put().to(update_process_endpoint))
-                    .route(web::delete().to(delete_process_endpoint))
-                    .route(web::patch().to(patch_process_endpoint)),
-            )
-            .service(
-                web::resource("/processes")
-                    .route(web::get().to(get_processes))
-                    .route(web::put().to(put_processes)),
-            )
-            .service(start_stop_consumers)
+            .configure(|cfg| api_routes(cfg, ready_max_age_secs))
     })
     .bind(&server_str)?
     .run()
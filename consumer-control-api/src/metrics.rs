@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::{ready, Ready};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{FutureExt, LocalBoxFuture};
+use once_cell::sync::Lazy;
+
+use crate::namespace::Namespaces;
+
+/// Process-wide metrics, rendered in the Prometheus text format by `/metrics`.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Upper bounds in seconds; the last ones cover long-polling watchers.
+const LATENCY_BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0, 30.0, 60.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    // (method, route, status) -> count
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    // (method, route) -> latency
+    latencies: Mutex<BTreeMap<(String, String), Histogram>>,
+    refresh_failures: AtomicU64,
+    write_conflicts: AtomicU64,
+    audit_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        *self.requests.lock().unwrap()
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+        let seconds = elapsed.as_secs_f64();
+        let mut latencies = self.latencies.lock().unwrap();
+        let histogram = latencies.entry((method.to_string(), route.to_string())).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    pub fn refresh_failed(&self) {
+        self.refresh_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn write_conflict(&self) {
+        self.write_conflicts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn audit_failed(&self) {
+        self.audit_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, namespaces: &Namespaces) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP http_requests_total Requests handled, by route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(out, "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}", method, route, status, count);
+        }
+        out.push_str("# HELP http_request_duration_seconds Time spent handling requests, by route.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in self.latencies.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+            }
+            let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+        out.push_str("# HELP cache_age_seconds Seconds since the processes were last confirmed against the store.\n");
+        out.push_str("# TYPE cache_age_seconds gauge\n");
+        for ns in namespaces.iter() {
+            let _ = writeln!(out, "cache_age_seconds{{namespace=\"{}\"}} {}", ns.name, ns.view.age_secs());
+        }
+        out.push_str("# HELP cache_etag_info Version of the stored document currently cached.\n");
+        out.push_str("# TYPE cache_etag_info gauge\n");
+        for ns in namespaces.iter() {
+            let etag = ns.view.etag().replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = writeln!(out, "cache_etag_info{{namespace=\"{}\",etag=\"{}\"}} 1", ns.name, etag);
+        }
+        out.push_str("# HELP cache_degraded 1 while serving a last-known-good snapshot the store has not confirmed.\n");
+        out.push_str("# TYPE cache_degraded gauge\n");
+        for ns in namespaces.iter() {
+            let _ = writeln!(out, "cache_degraded{{namespace=\"{}\"}} {}", ns.name, ns.view.is_degraded() as u8);
+        }
+        out.push_str("# HELP cache_refresh_failures_total Background refreshes that failed to reach the store.\n");
+        out.push_str("# TYPE cache_refresh_failures_total counter\n");
+        let _ = writeln!(out, "cache_refresh_failures_total {}", self.refresh_failures.load(Ordering::Relaxed));
+        out.push_str("# HELP write_conflicts_total Writes rejected because another writer saved first.\n");
+        out.push_str("# TYPE write_conflicts_total counter\n");
+        let _ = writeln!(out, "write_conflicts_total {}", self.write_conflicts.load(Ordering::Relaxed));
+        out.push_str("# HELP audit_write_failures_total Saved changes whose audit entries could not be appended.\n");
+        out.push_str("# TYPE audit_write_failures_total counter\n");
+        let _ = writeln!(out, "audit_write_failures_total {}", self.audit_failures.load(Ordering::Relaxed));
+        out.push_str("# HELP processes Registered processes, by their current run state.\n");
+        out.push_str("# TYPE processes gauge\n");
+        let now = chrono::Utc::now();
+        for ns in namespaces.iter() {
+            let (running, stopped) = ns.view.run_counts(now);
+            let _ = writeln!(out, "processes{{namespace=\"{}\",state=\"running\"}} {}", ns.name, running);
+            let _ = writeln!(out, "processes{{namespace=\"{}\",state=\"stopped\"}} {}", ns.name, stopped);
+        }
+        out
+    }
+}
+
+/// Middleware recording the count and latency of every request in `METRICS`.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        // The route pattern rather than the path, so query values and
+        // unknown paths don't create a series each.
+        let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        self.service.call(req)
+            .map(move |result| {
+                let status = match &result {
+                    Ok(res) => res.status(),
+                    Err(e) => e.as_response_error().status_code(),
+                };
+                METRICS.observe_request(&method, &route, status.as_u16(), started.elapsed());
+                result
+            })
+            .boxed_local()
+    }
+}
+
+#[tokio::test]
+async fn test_render_request_metrics() {
+    let metrics = Metrics::default();
+    metrics.observe_request("GET", "/process", 200, Duration::from_millis(20));
+    metrics.observe_request("GET", "/process", 200, Duration::from_secs(2));
+    metrics.write_conflict();
+    metrics.audit_failed();
+    let namespaces = Namespaces::in_memory(&["default", "qa"]).await;
+
+    let rendered = metrics.render(&namespaces);
+    assert!(rendered.contains("http_requests_total{method=\"GET\",route=\"/process\",status=\"200\"} 2\n"));
+    assert!(rendered.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/process\",le=\"0.025\"} 1\n"));
+    assert!(rendered.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/process\",le=\"+Inf\"} 2\n"));
+    assert!(rendered.contains("cache_etag_info{namespace=\"qa\",etag=\"1\"} 1\n"));
+    assert!(rendered.contains("processes{namespace=\"default\",state=\"running\"} 0\n"));
+    assert!(rendered.contains("write_conflicts_total 1\n"));
+    assert!(rendered.contains("audit_write_failures_total 1\n"));
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{web, FromRequest, HttpRequest};
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::cache::{self, MyCache, ProcessView};
+use crate::config::StorageConfig;
+use crate::error::ControlApiError;
+use crate::store::{self, LastKnownGood};
+
+/// Served by the routes without a `/ns/{namespace}` prefix.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Path segment naming the namespace in `/ns/{namespace}/...` routes.
+const NAMESPACE_PARAM: &str = "namespace";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NamespaceConfig {
+    /// s3://bucket/key, a local file path or memory://.
+    store: String,
+    snapshot_file: Option<PathBuf>,
+}
+
+/// The processes of one namespace, cached and refreshed independently of
+/// every other namespace.
+#[derive(Clone)]
+pub struct Namespace {
+    pub name: String,
+    pub cache: Arc<Mutex<MyCache>>,
+    pub view: Arc<ProcessView>,
+}
+
+/// Every namespace configured at startup, by name.
+pub struct Namespaces {
+    namespaces: BTreeMap<String, Namespace>,
+}
+
+impl Namespaces {
+    /// Reads a JSON file of `{"<name>": {"store": ..., "snapshot_file": ...}}`
+    /// entries. Only names are validated here; stores are opened by `open`.
+    fn read_file(path: &Path) -> Result<BTreeMap<String, NamespaceConfig>, String> {
+        let contents = std::fs::read(path).map_err(|e| format!("Failed to read namespaces file {}: {}", path.display(), e))?;
+        let configs: BTreeMap<String, NamespaceConfig> = serde_json::from_slice(&contents)
+            .map_err(|e| format!("Invalid namespaces file {}: {}", path.display(), e))?;
+        for name in configs.keys() {
+            validate_name(name).map_err(|e| format!("Invalid namespaces file {}: {}", path.display(), e))?;
+        }
+        Ok(configs)
+    }
+
+    /// Opens the store of every namespace and starts refreshing its cache.
+    /// `storage.location` becomes the `default` namespace, which may also be
+    /// declared in the namespaces file but not both.
+    pub async fn open(storage: &StorageConfig) -> Result<Self, String> {
+        let mut configs = match &storage.namespaces_file {
+            Some(path) => Self::read_file(path)?,
+            None => BTreeMap::new(),
+        };
+        if let Some(location) = &storage.location {
+            let config = NamespaceConfig { store: location.clone(), snapshot_file: storage.snapshot_file.clone() };
+            if configs.insert(DEFAULT_NAMESPACE.to_string(), config).is_some() {
+                return Err(format!("The {} namespace is configured both by the store and the namespaces file", DEFAULT_NAMESPACE));
+            }
+        } else if let Some(path) = &storage.snapshot_file {
+            warn!("Not using the snapshot file {}, there is no store to keep a snapshot of", path.display());
+        }
+        if configs.is_empty() {
+            return Err("Either a store or a namespaces file must be configured".to_string());
+        }
+
+        let mut namespaces = BTreeMap::new();
+        for (name, config) in configs {
+            let store = store::open_store(&config.store).await
+                .map_err(|e| format!("Failed to open the store of namespace {}: {}", name, e))?;
+            let snapshot = config.snapshot_file.map(LastKnownGood::new);
+            let cached_data = MyCache::new(store, storage.max_staleness_secs, snapshot).await
+                .map_err(|e| format!("Failed to read the processes of namespace {}: {}", name, e))?;
+            let view = cached_data.view();
+            let cached_data = Arc::new(Mutex::new(cached_data));
+            cache::spawn_refresher(cached_data.clone(), Duration::from_secs(storage.refresh_interval_secs));
+            info!("Serving namespace {}", name);
+            namespaces.insert(name.clone(), Namespace { name, cache: cached_data, view });
+        }
+        Ok(Namespaces { namespaces })
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Namespace, ControlApiError> {
+        self.namespaces.get(name).ok_or_else(|| ControlApiError::NamespaceNotFound(name.to_string()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Namespace> {
+        self.namespaces.values()
+    }
+
+    #[cfg(test)]
+    pub async fn in_memory(names: &[&str]) -> Self {
+        let mut namespaces = BTreeMap::new();
+        for name in names {
+            let cache = MyCache::new(Arc::new(store::MemoryStore::new(Default::default())), 0, None).await.unwrap();
+            let view = cache.view();
+            namespaces.insert(name.to_string(), Namespace { name: name.to_string(), cache: Arc::new(Mutex::new(cache)), view });
+        }
+        Namespaces { namespaces }
+    }
+}
+
+/// Names end up in paths and metric labels, so keep them to a safe alphabet.
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("namespace '{}' may only contain letters, digits, '-' and '_'", name));
+    }
+    Ok(())
+}
+
+/// The namespace named in the path, or the default one for routes without
+/// a `/ns/{namespace}` prefix.
+impl FromRequest for Namespace {
+    type Error = ControlApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let name = req.match_info().get(NAMESPACE_PARAM).unwrap_or(DEFAULT_NAMESPACE);
+        let result = match req.app_data::<web::Data<Namespaces>>() {
+            Some(namespaces) => namespaces.get(name).cloned(),
+            None => Err(ControlApiError::Internal("namespaces are not configured".to_string())),
+        };
+        ready(result)
+    }
+}
+
+#[actix_web::test]
+async fn test_namespace_from_path() {
+    let app = actix_web::test::init_service(
+        actix_web::App::new()
+            .app_data(web::Data::new(Namespaces::in_memory(&["default", "qa"]).await))
+            .route("/name", web::get().to(|ns: Namespace| async move { ns.name }))
+            .route("/ns/{namespace}/name", web::get().to(|ns: Namespace| async move { ns.name })),
+    ).await;
+
+    for (path, status, body) in [("/name", 200, "default"), ("/ns/qa/name", 200, "qa"), ("/ns/prod/name", 404, "")] {
+        let resp = actix_web::test::call_service(&app, actix_web::test::TestRequest::get().uri(path).to_request()).await;
+        assert_eq!(resp.status().as_u16(), status, "{}", path);
+        if status == 200 {
+            assert_eq!(actix_web::test::read_body(resp).await, body);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use actix_web::{web, HttpResponse};
+use serde_json::json;
+use utoipa::openapi::path::Operation;
+use utoipa::openapi::response::{Response, ResponseBuilder};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, Server};
+use utoipa::{Modify, OpenApi, ToResponse};
+
+use crate::auth::API_KEY_HEADER;
+use crate::error::GenericErrorResponse;
+
+/// The OpenAPI document, generated from the `#[utoipa::path]` annotations on
+/// the handlers and the schemas of the types they read and write.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Consumer Control API", version = "1.0.0", description = include_str!("../docs/description.md")),
+    servers(
+        (url = "/", description = "The default namespace"),
+        (url = "/ns/{namespace}", description = "A namespace configured on the server",
+            variables(("namespace" = (default = "default", description = "Name of the namespace")))),
+    ),
+    paths(
+        crate::get_json_value,
+        crate::add_process_endpoint,
+        crate::update_process_endpoint,
+        crate::delete_process_endpoint,
+        crate::patch_process_endpoint,
+        crate::rename_process_endpoint,
+        crate::get_process_history,
+        crate::change_process_tags,
+        crate::change_tags,
+        crate::list_tags,
+        crate::watch_processes,
+        crate::get_processes,
+        crate::put_processes,
+        crate::delete_processes_endpoint,
+        crate::start_stop_consumers,
+        crate::control_group,
+        crate::get_process_versions,
+        crate::diff_process_versions,
+        crate::rollback_processes,
+        crate::healthz,
+        crate::readyz,
+        crate::metrics_endpoint,
+    ),
+    components(
+        schemas(GenericErrorResponse),
+        responses(Unauthorized, Forbidden, BadRequest, NotFound, VersionNotFound, Conflict, ValidationFailed, StorageUnavailable),
+    ),
+    modifiers(&SecuritySchemes, &Probes),
+    security(("ApiKey" = []), ("BearerToken" = [])),
+    tags(
+        (name = "Single Process", description = "Operations related to a single process"),
+        (name = "Complex", description = "Operations to handle multiple processes"),
+        (name = "Versions", description = "Earlier versions of the stored processes"),
+        (name = "Tags", description = "Tags added to and removed from processes, and the tags in use"),
+        (name = "Groups", description = "Processes started and stopped together in dependency order"),
+        (name = "Operations", description = "Probes for load balancers and monitoring; no credentials needed"),
+    ),
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("ApiKey", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))));
+        components.add_security_scheme("BearerToken", SecurityScheme::Http(
+            HttpBuilder::new()
+                .scheme(HttpAuthScheme::Bearer)
+                .bearer_format("JWT")
+                .description(Some("HS256 token signed with one of the server's keys, carrying `sub`, `role` (`read_only` or `operator`) and `exp` claims."))
+                .build(),
+        ));
+    }
+}
+
+/// The probes are only served at the root and need no credentials.
+struct Probes;
+
+pub const PROBE_PATHS: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+impl Modify for Probes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for path in PROBE_PATHS {
+            if let Some(item) = openapi.paths.paths.get_mut(path) {
+                item.servers = Some(vec![Server::new("/")]);
+                item.get.iter_mut().for_each(|operation: &mut Operation| operation.security = Some(Vec::new()));
+            }
+        }
+    }
+}
+
+/// A response carrying a `GenericErrorResponse`, shared by the operations
+/// that can fail the same way.
+fn error_response(description: &str, example: serde_json::Value) -> RefOr<Response> {
+    ResponseBuilder::new()
+        .description(description)
+        .content("application/json", ContentBuilder::new()
+            .schema(Some(Ref::from_schema_name("GenericErrorResponse")))
+            .example(Some(example))
+            .build())
+        .build()
+        .into()
+}
+
+macro_rules! error_responses {
+    ($($name:ident => ($description:expr, $example:expr)),* $(,)?) => {
+        $(
+            pub struct $name;
+
+            impl<'r> ToResponse<'r> for $name {
+                fn response() -> (&'r str, RefOr<Response>) {
+                    (stringify!($name), error_response($description, $example))
+                }
+            }
+        )*
+    };
+}
+
+error_responses! {
+    Unauthorized => ("Credentials are missing or not valid",
+        json!({ "code": 401, "error": "unauthorized", "message": "Missing credentials" })),
+    Forbidden => ("The caller only has the read_only role",
+        json!({ "code": 403, "error": "forbidden", "message": "PATCH /processes/stop requires the operator role, consumer has read_only" })),
+    BadRequest => ("Malformed JSON body, unknown query parameter or invalid action",
+        json!({ "code": 400, "error": "bad_request", "message": "Invalid input: 'halt'. Expected 'start' or 'stop'." })),
+    NotFound => ("The process does not exist",
+        json!({ "code": 404, "error": "process_not_found", "message": "Process with name processf does not exist" })),
+    VersionNotFound => ("The version does not exist or is no longer retained",
+        json!({ "code": 404, "error": "version_not_found", "message": "Version 20240301T120000.000000000Z-0a1b2c3d4e5f6a7b of the processes does not exist" })),
+    Conflict => ("`process_already_exists` when registering or renaming to a name that is already taken.  \n\
+        `confirmation_mismatch` when a bulk delete selects another number of processes than `confirm`.  \n\
+        `write_conflict` when the stored processes kept being changed by other writers while this request was being applied, \
+        and re-applying it on the latest version did not succeed within the retry limit; nothing was saved. \
+        `current` then holds the latest stored state of the processes the request touched.",
+        json!({ "code": 409, "error": "write_conflict", "message": "stored document has changed since version \"9b2cf535f27731c974343645a3985328\", please retry the operation",
+            "current": [{ "name": "processf", "run": false, "effective": "2024-03-01T12:00:00" }] })),
+    ValidationFailed => ("The request body is well formed but not a valid change. When fields break a rule, \
+        `fields` lists every one of them.",
+        json!({ "code": 422, "error": "validation_failed", "message": "Invalid request: name must only contain letters, digits, '-', '_' and '.', not '*'",
+            "fields": [{ "field": "name", "message": "must only contain letters, digits, '-', '_' and '.', not '*'" }] })),
+    StorageUnavailable => ("The change could not be saved because the storage backend is unavailable; nothing was saved.  \n\
+        For reads, the processes have not been refreshed from the storage backend within the server's staleness limit.  \n\
+        While the server is degraded, every change is refused.",
+        json!({ "code": 503, "error": "storage_unavailable", "message": "changes were not saved: storage backend error: dispatch failure" })),
+}
+
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Serves the generated document next to the static files under `/docs`,
+/// so it has to be registered before them.
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/docs/openapi.json", web::get().to(openapi_json));
+}
+
+/// docs/openapi.yml is kept for readers without a running server.
+#[test]
+fn test_spec_file_is_current() {
+    const SPEC_FILE: &str = "docs/openapi.yml";
+    let spec = ApiDoc::openapi().to_yaml().unwrap();
+    // Regenerate with `UPDATE_OPENAPI=1 cargo test`.
+    if std::env::var_os("UPDATE_OPENAPI").is_some() {
+        std::fs::write(SPEC_FILE, &spec).unwrap();
+    }
+    let written = std::fs::read_to_string(SPEC_FILE).unwrap_or_default();
+    assert!(written == spec, "{} is stale, regenerate it with UPDATE_OPENAPI=1 cargo test", SPEC_FILE);
+}
+
+/// Every documented operation is routed and every other method on a
+/// documented path is not, at the root and under `/ns/{namespace}`.
+#[actix_web::test]
+async fn test_routes_match_spec() {
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+
+    let app = init_service(
+        actix_web::App::new()
+            .app_data(web::Data::new(crate::Namespaces::in_memory(&["default"]).await))
+            .configure(routes)
+            .configure(|cfg| crate::api_routes(cfg, u64::MAX)),
+    ).await;
+
+    let spec = ApiDoc::openapi();
+    let has_operation = |item: &utoipa::openapi::PathItem, method: &Method| match *method {
+        Method::GET => item.get.is_some(),
+        Method::POST => item.post.is_some(),
+        Method::PUT => item.put.is_some(),
+        Method::DELETE => item.delete.is_some(),
+        Method::PATCH => item.patch.is_some(),
+        _ => unreachable!(),
+    };
+    // `/processes/diff` is also matched by `/processes/{action}`.
+    let matches = |template: &str, path: &str| {
+        template.split('/').count() == path.split('/').count()
+            && template.split('/').zip(path.split('/')).all(|(t, p)| t == p || t.starts_with('{'))
+    };
+    let documented = |path: &str, method: &Method| spec.paths.paths.iter()
+        .any(|(template, item)| matches(template, path) && has_operation(item, method));
+
+    for template in spec.paths.paths.keys() {
+        let path = template.replace("{action}", "start").replace("{group}", "etl");
+        let mut prefixes = vec![""];
+        if !PROBE_PATHS.contains(&path.as_str()) {
+            prefixes.push("/ns/default");
+        }
+        for prefix in prefixes {
+            let uri = format!("{}{}", prefix, path);
+            for method in [Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH] {
+                let resp = call_service(&app, TestRequest::default().method(method.clone()).uri(&uri).to_request()).await;
+                let status = resp.status();
+                // Handlers answer a missing process or group with a body; the router does not.
+                let routed = match status {
+                    StatusCode::METHOD_NOT_ALLOWED => false,
+                    StatusCode::NOT_FOUND => !read_body(resp).await.is_empty(),
+                    _ => true,
+                };
+                assert_eq!(routed, documented(&path, &method), "{} {} answered {}", method, uri, status);
+            }
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use std::cmp::Ordering;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::Process;
+
+/// Most processes returned in one page.
+pub const MAX_LIMIT: usize = 1000;
+
+/// Deepest nesting of parentheses and `NOT` a filter may use, so a hostile
+/// expression cannot exhaust the stack of the parser.
+pub const MAX_DEPTH: usize = 64;
+
+/// Which processes a query selects, compiled once per request.
+///
+/// The `filter` expression combines terms with `AND` (also implied between
+/// adjacent terms), `OR`, `NOT` and parentheses:
+///
+/// - `tag:dmi` has the tag
+/// - `name:proc*` matches the glob, `name~^proc-[0-9]+$` the regex
+/// - `run:true` has that run state
+/// - `owner:`, `team:`, `contact:`, `description:` and `reason:` equal the
+///   value, or match the regex with `~` instead of `:`
+/// - `label:env=prod` has the label with that value, `label:env` with any value
+/// - `effective>=2024-03-01` was last changed at or after the time, also
+///   with `>`, `<` and `<=`; times are local like `effective` itself
+///
+/// Values containing spaces or parentheses can be double-quoted.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Tag(String),
+    NameGlob(Pattern),
+    NameRegex(Regex),
+    Run(bool),
+    Text(TextField, String),
+    TextRegex(TextField, Regex),
+    Label(String, Option<String>),
+    Effective(Ordering, bool, NaiveDateTime),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(expr: &str) -> Result<Filter, String> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens, pos: 0, depth: 0 };
+        let filter = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(filter),
+            Some(token) => Err(format!("unexpected '{}'", token.text)),
+        }
+    }
+
+    /// Processes carrying every one of `tags`.
+    pub fn all_tags(tags: &[String]) -> Filter {
+        Filter::And(tags.iter().cloned().map(Filter::Tag).collect())
+    }
+
+    /// Processes carrying at least one of `tags`.
+    pub fn any_tag(tags: &[String]) -> Filter {
+        Filter::Or(tags.iter().cloned().map(Filter::Tag).collect())
+    }
+
+    /// Processes whose name matches at least one of the glob `patterns`.
+    pub fn name_patterns(patterns: &[String]) -> Result<Filter, String> {
+        patterns.iter()
+            .map(|p| Pattern::new(p).map(Filter::NameGlob).map_err(|e| format!("invalid name pattern '{}': {}", p, e)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Filter::Or)
+    }
+
+    pub fn matches(&self, process: &Process) -> bool {
+        match self {
+            Filter::Tag(tag) => process.tags.as_ref().is_some_and(|tags| tags.contains(tag)),
+            Filter::NameGlob(pattern) => pattern.matches(&process.name),
+            Filter::NameRegex(regex) => regex.is_match(&process.name),
+            Filter::Run(run) => process.run == *run,
+            Filter::Text(field, value) => field.value(process) == Some(value.as_str()),
+            Filter::TextRegex(field, regex) => field.value(process).is_some_and(|v| regex.is_match(v)),
+            Filter::Label(key, value) => process.metadata.labels.as_ref()
+                .and_then(|labels| labels.get(key))
+                .is_some_and(|v| value.as_ref().is_none_or(|value| v == value)),
+            Filter::Effective(ordering, or_equal, at) => {
+                parse_time(&process.effective)
+                    .is_ok_and(|effective| {
+                        let cmp = effective.cmp(at);
+                        cmp == *ordering || (*or_equal && cmp == Ordering::Equal)
+                    })
+            },
+            Filter::And(filters) => filters.iter().all(|f| f.matches(process)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(process)),
+            Filter::Not(filter) => !filter.matches(process),
+        }
+    }
+}
+
+/// A free text field of a process that filters can match.
+#[derive(Debug, Clone, Copy)]
+pub enum TextField {
+    Owner,
+    Team,
+    Contact,
+    Description,
+    Reason,
+    Group,
+}
+
+impl TextField {
+    fn parse(name: &str) -> Option<TextField> {
+        match name {
+            "owner" => Some(TextField::Owner),
+            "team" => Some(TextField::Team),
+            "contact" => Some(TextField::Contact),
+            "description" => Some(TextField::Description),
+            "reason" => Some(TextField::Reason),
+            "group" => Some(TextField::Group),
+            _ => None,
+        }
+    }
+
+    fn value(self, process: &Process) -> Option<&str> {
+        let metadata = &process.metadata;
+        match self {
+            TextField::Owner => metadata.owner.as_deref(),
+            TextField::Team => metadata.team.as_deref(),
+            TextField::Contact => metadata.contact.as_deref(),
+            TextField::Description => metadata.description.as_deref(),
+            TextField::Reason => process.reason.as_deref(),
+            TextField::Group => process.group.as_deref(),
+        }
+    }
+}
+
+struct Token {
+    text: String,
+    // Quoted text is always a value, never an operator or parenthesis.
+    quoted: bool,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(Token { text: c.to_string(), quoted: false });
+            continue;
+        }
+        let mut token = Token { text: String::new(), quoted: false };
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+            chars.next();
+            if c != '"' {
+                token.text.push(c);
+                continue;
+            }
+            token.quoted = true;
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => token.text.extend(chars.next()),
+                    Some(c) => token.text.push(c),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn keyword(&self, keyword: &str) -> bool {
+        self.tokens.get(self.pos).is_some_and(|t| !t.quoted && t.text.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, String> {
+        let mut filters = vec![self.parse_and()?];
+        while self.keyword("OR") {
+            self.pos += 1;
+            filters.push(self.parse_and()?);
+        }
+        Ok(if filters.len() == 1 { filters.remove(0) } else { Filter::Or(filters) })
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, String> {
+        let mut filters = vec![self.parse_unary()?];
+        loop {
+            if self.keyword("AND") {
+                self.pos += 1;
+            } else if self.pos == self.tokens.len() || self.keyword("OR") || self.keyword(")") {
+                break;
+            }
+            filters.push(self.parse_unary()?);
+        }
+        Ok(if filters.len() == 1 { filters.remove(0) } else { Filter::And(filters) })
+    }
+
+    /// Runs `parse` one level deeper, failing past `MAX_DEPTH`.
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Filter, String>) -> Result<Filter, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("expression is nested more than {} levels deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let filter = parse(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter, String> {
+        if self.keyword("NOT") {
+            self.pos += 1;
+            return Ok(Filter::Not(Box::new(self.nested(Parser::parse_unary)?)));
+        }
+        if self.keyword("(") {
+            self.pos += 1;
+            let filter = self.nested(Parser::parse_or)?;
+            if !self.keyword(")") {
+                return Err("missing ')'".to_string());
+            }
+            self.pos += 1;
+            return Ok(filter);
+        }
+        let token = self.tokens.get(self.pos).ok_or("expression ends early")?;
+        if !token.quoted && ["AND", "OR", ")"].iter().any(|k| token.text.eq_ignore_ascii_case(k)) {
+            return Err(format!("unexpected '{}'", token.text));
+        }
+        self.pos += 1;
+        parse_term(&token.text)
+    }
+}
+
+fn parse_term(term: &str) -> Result<Filter, String> {
+    let split = term.find([':', '~', '<', '>'])
+        .ok_or_else(|| format!("'{}' is not a term like tag:value", term))?;
+    let (field, rest) = term.split_at(split);
+    let (op, value) = [">=", "<=", ":", "~", "<", ">"].iter()
+        .find_map(|op| rest.strip_prefix(op).map(|value| (*op, value)))
+        .unwrap_or(("", rest));
+    if value.is_empty() {
+        return Err(format!("'{}' has no value; quote values containing spaces or parentheses", term));
+    }
+    if let Some(text) = TextField::parse(field) {
+        return match op {
+            ":" => Ok(Filter::Text(text, value.to_string())),
+            "~" => Regex::new(value).map(|regex| Filter::TextRegex(text, regex))
+                .map_err(|e| format!("invalid regex '{}': {}", value, e)),
+            _ => Err(format!("unknown term '{}'", term)),
+        };
+    }
+    match (field, op) {
+        ("tag", ":") => Ok(Filter::Tag(value.to_string())),
+        ("name", ":") => Pattern::new(value).map(Filter::NameGlob)
+            .map_err(|e| format!("invalid name pattern '{}': {}", value, e)),
+        ("name", "~") => Regex::new(value).map(Filter::NameRegex)
+            .map_err(|e| format!("invalid name regex '{}': {}", value, e)),
+        ("run", ":") => value.parse().map(Filter::Run)
+            .map_err(|_| format!("run must be true or false, got '{}'", value)),
+        ("label", ":") => Ok(parse_label(value)),
+        ("effective", ">" | ">=" | "<" | "<=") => {
+            let ordering = if op.starts_with('>') { Ordering::Greater } else { Ordering::Less };
+            Ok(Filter::Effective(ordering, op.ends_with('='), parse_time(value)?))
+        },
+        _ => Err(format!("unknown term '{}'", term)),
+    }
+}
+
+/// `key=value` matches that value, a bare `key` any value.
+pub fn parse_label(label: &str) -> Filter {
+    match label.split_once('=') {
+        Some((key, value)) => Filter::Label(key.to_string(), Some(value.to_string())),
+        None => Filter::Label(label.to_string(), None),
+    }
+}
+
+/// Accepts a date, a local date and time, or an RFC 3339 time converted to local.
+fn parse_time(value: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(value) {
+        return Ok(t.with_timezone(&Local).naive_local());
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+        .ok_or_else(|| format!("invalid time '{}'", value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortField {
+    Name,
+    Run,
+    Effective,
+}
+
+/// Comma separated fields to sort by, each descending when prefixed with
+/// `-`. Ties are always broken by name, so the order is stable across pages.
+#[derive(Debug, Clone)]
+pub struct SortSpec {
+    spec: String,
+    keys: Vec<(SortField, bool)>,
+}
+
+impl SortSpec {
+    pub fn parse(spec: &str) -> Result<SortSpec, String> {
+        let mut keys = Vec::new();
+        for key in spec.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            let (descending, name) = match key.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, key),
+            };
+            let field = match name {
+                "name" => SortField::Name,
+                "run" => SortField::Run,
+                "effective" => SortField::Effective,
+                _ => return Err(format!("cannot sort by '{}', expected name, run or effective", name)),
+            };
+            keys.push((field, descending));
+        }
+        if !keys.iter().any(|(field, _)| *field == SortField::Name) {
+            keys.push((SortField::Name, false));
+        }
+        Ok(SortSpec { spec: spec.to_string(), keys })
+    }
+
+    fn compare(&self, a: &SortKey, b: &SortKey) -> Ordering {
+        self.keys.iter()
+            .map(|(field, descending)| {
+                let directed = |cmp: Ordering| if *descending { cmp.reverse() } else { cmp };
+                match field {
+                    SortField::Name => directed(a.name.cmp(&b.name)),
+                    SortField::Run => directed(a.run.cmp(&b.run)),
+                    // Values that are not a time go last in either direction.
+                    SortField::Effective => match (parse_time(&a.effective).ok(), parse_time(&b.effective).ok()) {
+                        (Some(a), Some(b)) => directed(a.cmp(&b)),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => directed(a.effective.cmp(&b.effective)),
+                    },
+                }
+            })
+            .find(|cmp| cmp.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Default for SortSpec {
+    fn default() -> Self {
+        SortSpec { spec: String::new(), keys: vec![(SortField::Name, false)] }
+    }
+}
+
+/// The sortable values of the last process on a page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SortKey {
+    name: String,
+    run: bool,
+    effective: String,
+}
+
+impl From<&Process> for SortKey {
+    fn from(p: &Process) -> Self {
+        SortKey { name: p.name.clone(), run: p.run, effective: p.effective.clone() }
+    }
+}
+
+/// Where the next page starts; only valid with the sort it was made for.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    after: SortKey,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str) -> Result<Cursor, String> {
+        URL_SAFE_NO_PAD.decode(cursor).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| "invalid cursor".to_string())
+    }
+}
+
+const FIELDS: [&str; 13] = ["name", "run", "tags", "effective", "schedule", "group", "depends_on", "owner", "team", "description", "contact", "labels", "reason"];
+
+/// Sorting, paging and projection of the processes a query selected.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub sort: SortSpec,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    /// Fields to return; all of them when `None`.
+    pub fields: Option<Vec<String>>,
+}
+
+impl ListOptions {
+    pub fn new(sort: Option<&str>, limit: Option<usize>, cursor: Option<String>, fields: Option<&str>) -> Result<ListOptions, String> {
+        if limit.is_some_and(|limit| limit == 0 || limit > MAX_LIMIT) {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+        let fields = fields.map(|fields| fields.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect::<Vec<_>>());
+        if let Some(unknown) = fields.iter().flatten().find(|f| !FIELDS.contains(&f.as_str())) {
+            return Err(format!("unknown field '{}', expected one of {}", unknown, FIELDS.join(", ")));
+        }
+        Ok(ListOptions {
+            sort: sort.map(SortSpec::parse).transpose()?.unwrap_or_default(),
+            limit,
+            cursor,
+            fields,
+        })
+    }
+
+    /// Sorts `processes` and returns the requested page, projected, with the
+    /// cursor of the next page when there is one.
+    pub fn apply(&self, processes: Vec<Process>) -> Result<(Vec<serde_json::Value>, Option<String>), String> {
+        let mut keyed: Vec<(SortKey, Process)> = processes.into_iter().map(|p| (SortKey::from(&p), p)).collect();
+        keyed.sort_by(|(a, _), (b, _)| self.sort.compare(a, b));
+        if let Some(cursor) = &self.cursor {
+            let cursor = Cursor::decode(cursor)?;
+            if cursor.sort != self.sort.spec {
+                return Err(format!("cursor was made for sort '{}'", cursor.sort));
+            }
+            keyed.retain(|(key, _)| self.sort.compare(key, &cursor.after) == Ordering::Greater);
+        }
+        let next = match self.limit {
+            Some(limit) if keyed.len() > limit => {
+                keyed.truncate(limit);
+                keyed.last().map(|(key, _)| Cursor { sort: self.sort.spec.clone(), after: key.clone() }.encode())
+            },
+            _ => None,
+        };
+        let page = keyed.into_iter().map(|(_, p)| self.project(&p)).collect();
+        Ok((page, next))
+    }
+
+    fn project(&self, process: &Process) -> serde_json::Value {
+        let value = serde_json::to_value(process).unwrap_or_default();
+        match (&self.fields, value) {
+            (Some(fields), serde_json::Value::Object(mut object)) => {
+                object.retain(|key, _| fields.contains(key));
+                serde_json::Value::Object(object)
+            },
+            (_, value) => value,
+        }
+    }
+}
+
+#[test]
+fn test_filter_expression() {
+    use crate::cache::create_process;
+
+    let mut p1 = create_process("proc-1", true, Some(vec!["dmi".to_string(), "v4".to_string()]));
+    p1.effective = "2024-03-01 12:00:00".to_string();
+    let mut p2 = create_process("proc-22", false, Some(vec!["legacy".to_string()]));
+    p2.effective = "2024-02-01 12:00:00".to_string();
+    let mut p3 = create_process("other", true, None);
+    // Older documents store `effective` with a `T`.
+    p3.effective = "2024-02-20T09:34:41".to_string();
+    let names = |expr: &str| -> Vec<String> {
+        let filter = Filter::parse(expr).unwrap();
+        [&p1, &p2, &p3].into_iter().filter(|p| filter.matches(p)).map(|p| p.name.clone()).collect()
+    };
+    assert_eq!(names("tag:dmi OR tag:legacy"), vec!["proc-1", "proc-22"]);
+    assert_eq!(names("name:proc* AND NOT tag:legacy"), vec!["proc-1"]);
+    assert_eq!(names(r#"name~"^proc-[0-9]{2}$" run:false"#), vec!["proc-22"]);
+    assert_eq!(names("effective>=2024-02-15 (tag:v4 OR run:false)"), vec!["proc-1"]);
+    assert_eq!(names("effective>2024-02-15 effective<2024-02-21"), vec!["other"]);
+    assert!(Filter::parse(r#"name~"(""#).is_err());
+    assert!(Filter::parse("name:[").is_err());
+    assert!(Filter::parse("tag:dmi OR").is_err());
+    let nested = |depth: usize| format!("{}tag:dmi{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(names(&nested(MAX_DEPTH)), vec!["proc-1"]);
+    assert!(Filter::parse(&nested(10_000)).unwrap_err().contains("nested"));
+    assert!(Filter::parse(&"NOT ".repeat(10_000)).unwrap_err().contains("nested"));
+
+    let options = ListOptions::new(Some("-effective"), Some(1), None, Some("name")).unwrap();
+    let (page, next) = options.apply(vec![p1.clone(), p2.clone()]).unwrap();
+    assert_eq!(page, vec![serde_json::json!({ "name": "proc-1" })]);
+    let options = ListOptions { cursor: next, ..options };
+    let (page, next) = options.apply(vec![p1.clone(), p2.clone()]).unwrap();
+    assert_eq!(page, vec![serde_json::json!({ "name": "proc-22" })]);
+    assert!(next.is_none());
+
+    // `T` and space formats order by time, with what isn't a time last.
+    let mut p4 = create_process("unset", false, None);
+    p4.effective = "n/a".to_string();
+    let all = || vec![p4.clone(), p2.clone(), p3.clone(), p1.clone()];
+    let mut options = ListOptions::new(Some("-effective"), Some(2), None, Some("name")).unwrap();
+    let (page, next) = options.apply(all()).unwrap();
+    assert_eq!(page, vec![serde_json::json!({ "name": "proc-1" }), serde_json::json!({ "name": "other" })]);
+    options.cursor = next;
+    let (page, next) = options.apply(all()).unwrap();
+    assert_eq!(page, vec![serde_json::json!({ "name": "proc-22" }), serde_json::json!({ "name": "unset" })]);
+    assert!(next.is_none());
+}
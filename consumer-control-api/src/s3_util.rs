@@ -1,21 +1,8 @@
-use aws_sdk_s3::operation::{
-    copy_object::{CopyObjectError, CopyObjectOutput},
-    create_bucket::{CreateBucketError, CreateBucketOutput},
-    get_object::{GetObjectError, GetObjectOutput},
-    list_objects_v2::ListObjectsV2Output,
-    put_object::{PutObjectError, PutObjectOutput},
-    head_object::{HeadObjectError, HeadObjectOutput},
-};
-
-use aws_sdk_s3::types::{
-    BucketLocationConstraint, CreateBucketConfiguration, Delete, ObjectIdentifier,
-};
-
 use aws_config::meta::region::RegionProviderChain;
 
+use aws_sdk_s3::operation::put_object::PutObjectError;
 use aws_sdk_s3::{error::SdkError, primitives::ByteStream, Client};
-use std::path::Path;
-use std::str;
+use std::sync::Arc;
 
 pub async fn get_client() -> Result<Arc<Client>, Box<dyn std::error::Error>> {
     let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
@@ -24,36 +11,59 @@ pub async fn get_client() -> Result<Arc<Client>, Box<dyn std::error::Error>> {
     Ok(client)
 }
 
-use std::sync::Arc;
+pub fn parse_s3_filename(filename: &str) -> Option<(String, String)> {
+    let without_prefix = filename.strip_prefix("s3://")?;
+    let parts: Vec<&str> = without_prefix.splitn(2, '/').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+
+    let bucket = parts[0].to_string();
+    let key = parts[1].to_string();
 
-pub fn init_s3_client() -> Arc<Client> {
-    println!("Initializing S3 client...");
-    tokio::runtime::Runtime::new()
-        .expect("Failed to create Tokio runtime")
-        .block_on(async {
-            let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-            let config = aws_config::from_env().region(region_provider).load().await;
-            Arc::new(Client::new(&config))
-        })
+    Some((bucket, key))
 }
 
-pub async fn upload_object(
+#[test]
+fn test_parse_s3_filename() {
+    assert_eq!(
+        parse_s3_filename("s3://mybucket/API_CONTROL/processes.json"),
+        Some(("mybucket".to_string(), "API_CONTROL/processes.json".to_string()))
+    );
+    assert_eq!(parse_s3_filename("processes.json"), None);
+}
+
+/// Uploads `body` to `key` only if the object's current ETag is `etag`, or
+/// only if no object exists yet when `etag` is `None`.
+pub async fn put_object_conditional(
     client: &Client,
     bucket_name: &str,
-    file_name: &str,
     key: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let body = ByteStream::from_path(Path::new(file_name)).await;
-    let result = client
+    body: Vec<u8>,
+    etag: Option<&str>,
+) -> Result<String, SdkError<PutObjectError>> {
+    let request = client
         .put_object()
         .bucket(bucket_name)
         .key(key)
-        .body(body.unwrap())
-        .send()
-        .await?;
+        .body(ByteStream::from(body));
+    let request = match etag {
+        Some(etag) => request.if_match(etag),
+        None => request.if_none_match("*"),
+    };
+    let result = request.send().await?;
+
+    result.e_tag
+        .ok_or_else(|| SdkError::construction_failure("Failed to get ETag from PutObjectOutput"))
+}
 
-    match result.e_tag {
-        Some(etag) => Ok(etag),
-        None => Err("Failed to get ETag from PutObjectOutput".into()),
+/// True when S3 rejected a conditional write: 412 when the ETag no longer
+/// matches, 409 when another conditional write to the same key is in flight.
+pub fn is_precondition_failure<E>(err: &SdkError<E>) -> bool {
+    match err {
+        SdkError::ServiceError(service_err) => {
+            matches!(service_err.raw().status().as_u16(), 409 | 412)
+        },
+        _ => false,
     }
 }
@@ -0,0 +1,668 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_sdk_s3::Client;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::audit::{self, AuditEntry};
+use crate::cache::Process;
+use crate::s3_util::{is_precondition_failure, parse_s3_filename, put_object_conditional};
+
+pub use consumer_control_client::model::DocumentVersion;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("stored document has changed since version {0}, please retry the operation")]
+    Conflict(String),
+    #[error("invalid store location: {0}")]
+    InvalidLocation(String),
+    #[error("storage backend error: {0}")]
+    Backend(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid process document: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("no stored version {0}")]
+    VersionNotFound(String),
+}
+
+/// Persistence for the process document behind `MyCache`.
+///
+/// Every load hands back a version token (an ETag for S3, a content hash for
+/// files, a counter in memory) and `save` only succeeds when the stored
+/// document still carries the version the caller last saw.
+#[async_trait]
+pub trait ProcessStore: Send + Sync {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError>;
+
+    async fn current_version(&self) -> Result<String, StoreError>;
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError>;
+
+    /// Appends to the audit trail kept next to the document, grouped by UTC day.
+    async fn append_audit(&self, entries: &[AuditEntry]) -> Result<(), StoreError>;
+
+    /// Every audit entry recorded for `process_name` on the days in `days`, oldest first.
+    async fn load_audit(&self, process_name: &str, days: &DayRange) -> Result<Vec<AuditEntry>, StoreError>;
+
+    /// Every retained version of the document, newest first.
+    async fn list_versions(&self) -> Result<Vec<DocumentVersion>, StoreError> {
+        Err(StoreError::Backend(format!("{} does not retain versions", self.describe())))
+    }
+
+    /// The document as it was saved in `version_id`.
+    async fn load_version(&self, version_id: &str) -> Result<HashMap<String, Process>, StoreError> {
+        Err(StoreError::VersionNotFound(version_id.to_string()))
+    }
+
+    fn describe(&self) -> String;
+}
+
+/// Builds the store for `location`:
+/// `s3://bucket/key` for S3, `memory://` for an empty in-memory document,
+/// and a plain path (optionally prefixed with `file://`) for a local file.
+pub async fn open_store(location: &str) -> Result<Arc<dyn ProcessStore>, StoreError> {
+    let store: Arc<dyn ProcessStore> = if location.starts_with("s3://") {
+        let client = crate::s3_util::get_client().await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        Arc::new(S3Store::new(client, location)?)
+    } else if location.starts_with("memory://") {
+        Arc::new(MemoryStore::new(HashMap::new()))
+    } else {
+        let path = location.strip_prefix("file://").unwrap_or(location);
+        Arc::new(LocalStore::new(path))
+    };
+    info!("Using process store: {}", store.describe());
+    Ok(store)
+}
+
+fn to_list(processes: &HashMap<String, Process>) -> Vec<Process> {
+    let mut list: Vec<Process> = processes.values().cloned().collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
+
+fn to_map(processes: Vec<Process>) -> HashMap<String, Process> {
+    processes.into_iter().map(|p| (p.name.clone(), p)).collect()
+}
+
+/// The name of the document without directory and extension, which keys
+/// its audit trail and retained versions, so documents kept side by side for
+/// different namespaces never share them.
+fn document_stem(path: &str) -> &str {
+    std::path::Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or(path)
+}
+
+/// UTC days of the audit trail to read, both ends included; open ended
+/// where `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DayRange {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl DayRange {
+    /// `day` is formatted like `AuditEntry::day`, which sorts chronologically.
+    pub fn contains(&self, day: &str) -> bool {
+        !self.is_before(day) && !self.is_after(day)
+    }
+
+    fn is_before(&self, day: &str) -> bool {
+        self.since.is_some_and(|since| day < since.to_string().as_str())
+    }
+
+    fn is_after(&self, day: &str) -> bool {
+        self.until.is_some_and(|until| day > until.to_string().as_str())
+    }
+}
+
+fn group_by_day(entries: &[AuditEntry]) -> BTreeMap<String, Vec<AuditEntry>> {
+    let mut days: BTreeMap<String, Vec<AuditEntry>> = BTreeMap::new();
+    for entry in entries {
+        days.entry(entry.day().to_string()).or_default().push(entry.clone());
+    }
+    days
+}
+
+pub struct S3Store {
+    client: Arc<Client>,
+    s3_file_name: String,
+    bucket: String,
+    key: String,
+    audit_prefix: String,
+}
+
+impl S3Store {
+    pub fn new(client: Arc<Client>, s3_file_name: &str) -> Result<Self, StoreError> {
+        let (bucket, key) = parse_s3_filename(s3_file_name)
+            .ok_or_else(|| StoreError::InvalidLocation(s3_file_name.to_string()))?;
+        let audit_prefix = match key.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/audit/{}/", dir, document_stem(&key)),
+            None => format!("audit/{}/", document_stem(&key)),
+        };
+        Ok(S3Store {
+            client,
+            s3_file_name: s3_file_name.to_string(),
+            bucket,
+            key,
+            audit_prefix,
+        })
+    }
+
+    /// Reads `key`, returning `None` when it does not exist yet.
+    async fn get_bytes(&self, key: &str) -> Result<Option<(Vec<u8>, String)>, StoreError> {
+        let output = match self.client.get_object().bucket(&self.bucket).key(key).send().await {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(StoreError::Backend(DisplayErrorContext(e).to_string())),
+        };
+        let etag = output.e_tag.clone()
+            .ok_or_else(|| StoreError::Backend("Failed to get ETag from GetObjectOutput".to_string()))?;
+        let contents = output.body.collect().await
+            .map_err(|e| StoreError::Backend(e.to_string()))?
+            .into_bytes();
+        Ok(Some((contents.to_vec(), etag)))
+    }
+
+}
+
+#[async_trait]
+impl ProcessStore for S3Store {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        let (contents, etag) = self.get_bytes(&self.key).await?
+            .ok_or_else(|| StoreError::Backend(format!("{} does not exist", self.s3_file_name)))?;
+        info!("Downloaded s3 file with ETag: {}", etag);
+        let data: Vec<Process> = serde_json::from_slice(&contents)?;
+        Ok((to_map(data), etag))
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        let head_object_output = self.client.head_object().bucket(&self.bucket).key(&self.key).send().await
+            .map_err(|e| StoreError::Backend(DisplayErrorContext(e).to_string()))?;
+        head_object_output.e_tag
+            .ok_or_else(|| StoreError::Backend("Failed to get ETag from HeadObjectOutput".to_string()))
+    }
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError> {
+        let contents = serde_json::to_vec(&to_list(data))?;
+        // S3 evaluates If-Match atomically with the write, so a concurrent
+        // writer that got in first makes this put fail instead of being overwritten.
+        match put_object_conditional(&self.client, &self.bucket, &self.key, contents, Some(version)).await {
+            Ok(etag) => Ok(etag),
+            Err(e) if is_precondition_failure(&e) => Err(StoreError::Conflict(version.to_string())),
+            Err(e) => Err(StoreError::Backend(format!("Error uploading file: {}", DisplayErrorContext(e)))),
+        }
+    }
+
+    /// S3 cannot append, so every append is a new object under the prefix of
+    /// its day, named so the objects of a day sort chronologically.
+    async fn append_audit(&self, entries: &[AuditEntry]) -> Result<(), StoreError> {
+        let written_at = Utc::now().format(VERSION_TIME_FORMAT);
+        for (day, entries) in group_by_day(entries) {
+            let key = format!("{}{}/{}-{}.jsonl", self.audit_prefix, day, written_at, uuid::Uuid::new_v4().simple());
+            put_object_conditional(&self.client, &self.bucket, &key, audit::to_jsonl(&entries)?, None).await
+                .map_err(|e| StoreError::Backend(format!("Error writing {}: {}", key, DisplayErrorContext(e))))?;
+        }
+        Ok(())
+    }
+
+    async fn load_audit(&self, process_name: &str, days: &DayRange) -> Result<Vec<AuditEntry>, StoreError> {
+        let mut keys = Vec::new();
+        let mut request = self.client.list_objects_v2().bucket(&self.bucket).prefix(&self.audit_prefix);
+        if let Some(since) = days.since {
+            request = request.start_after(format!("{}{}", self.audit_prefix, since));
+        }
+        let mut pages = request.into_paginator().send();
+        // S3 lists keys in order, so the listing can stop at the first day past the range.
+        'pages: while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| StoreError::Backend(DisplayErrorContext(e).to_string()))?;
+            for key in page.contents().iter().filter_map(|o| o.key()) {
+                let day = key[self.audit_prefix.len()..].get(..10).unwrap_or_default();
+                if days.is_after(day) {
+                    break 'pages;
+                }
+                keys.push(key.to_string());
+            }
+        }
+        let mut entries = Vec::new();
+        for key in keys {
+            if let Some((contents, _)) = self.get_bytes(&key).await? {
+                entries.extend(audit::from_jsonl(&contents, process_name)?);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Relies on versioning being enabled on the bucket; without it S3 only
+    /// keeps the current object, listed with the version ID `null`.
+    async fn list_versions(&self) -> Result<Vec<DocumentVersion>, StoreError> {
+        let mut versions = Vec::new();
+        let (mut key_marker, mut version_id_marker) = (None, None);
+        loop {
+            let page = self.client.list_object_versions().bucket(&self.bucket).prefix(&self.key)
+                .set_key_marker(key_marker).set_version_id_marker(version_id_marker)
+                .send().await
+                .map_err(|e| StoreError::Backend(DisplayErrorContext(e).to_string()))?;
+            // The prefix also matches longer keys, and delete markers are listed separately.
+            versions.extend(page.versions().iter()
+                .filter(|v| v.key() == Some(self.key.as_str()))
+                .map(|v| DocumentVersion {
+                    version_id: v.version_id().unwrap_or("null").to_string(),
+                    etag: v.e_tag().unwrap_or_default().to_string(),
+                    saved_at: v.last_modified()
+                        .and_then(|t| t.fmt(aws_smithy_types::date_time::Format::DateTime).ok())
+                        .unwrap_or_default(),
+                    current: v.is_latest().unwrap_or(false),
+                }));
+            if !page.is_truncated().unwrap_or(false) {
+                break;
+            }
+            key_marker = page.next_key_marker().map(|m| m.to_string());
+            version_id_marker = page.next_version_id_marker().map(|m| m.to_string());
+        }
+        // S3 already lists the versions of a key newest first.
+        Ok(versions)
+    }
+
+    async fn load_version(&self, version_id: &str) -> Result<HashMap<String, Process>, StoreError> {
+        let output = match self.client.get_object().bucket(&self.bucket).key(&self.key).version_id(version_id).send().await {
+            Ok(output) => output,
+            // An unknown ID is NoSuchVersion, a malformed one InvalidArgument.
+            Err(e) if e.as_service_error().is_some_and(|e| matches!(e.code(), Some("NoSuchKey" | "NoSuchVersion" | "InvalidArgument"))) => {
+                return Err(StoreError::VersionNotFound(version_id.to_string()));
+            },
+            Err(e) => return Err(StoreError::Backend(DisplayErrorContext(e).to_string())),
+        };
+        let contents = output.body.collect().await
+            .map_err(|e| StoreError::Backend(e.to_string()))?
+            .into_bytes();
+        let data: Vec<Process> = serde_json::from_slice(&contents)?;
+        Ok(to_map(data))
+    }
+
+    fn describe(&self) -> String {
+        self.s3_file_name.clone()
+    }
+}
+
+/// Stores the document as a JSON file on the local filesystem, using a hash
+/// of the file contents as the version token. Every saved document is also
+/// kept as a timestamped copy under `versions/<stem>/`.
+pub struct LocalStore {
+    path: PathBuf,
+    audit_dir: PathBuf,
+    versions_dir: PathBuf,
+    // Serializes the compare-and-write within this process.
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+/// Names retained copies so they sort chronologically, even the two kept by
+/// one save; contains no `-`, which separates it from the version token.
+const VERSION_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.9fZ";
+
+impl LocalStore {
+    pub fn new(path: &str) -> Self {
+        let stem = document_stem(path);
+        let path = PathBuf::from(path);
+        let dir = path.parent().unwrap_or(std::path::Path::new(""));
+        LocalStore {
+            audit_dir: dir.join("audit").join(stem),
+            versions_dir: dir.join("versions").join(stem),
+            path,
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Keeps a copy of `contents`, identified by when it was saved and its version token.
+    async fn retain(&self, contents: &[u8]) -> Result<(), StoreError> {
+        tokio::fs::create_dir_all(&self.versions_dir).await?;
+        let version_id = format!("{}-{}", Utc::now().format(VERSION_TIME_FORMAT), content_version(contents));
+        tokio::fs::write(self.versions_dir.join(format!("{}.json", version_id)), contents).await?;
+        Ok(())
+    }
+
+    /// IDs of the retained copies, oldest first.
+    async fn version_ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut ids = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.versions_dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                ids.extend(path.file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.to_string()));
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn read_contents(&self) -> Result<Vec<u8>, StoreError> {
+        match tokio::fs::read(&self.path).await {
+            Ok(contents) => Ok(contents),
+            // A missing file is treated as an empty document so a fresh
+            // checkout can start without any setup.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(b"[]".to_vec()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn content_version(contents: &[u8]) -> String {
+    let digest = Sha256::digest(contents);
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[async_trait]
+impl ProcessStore for LocalStore {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        let contents = self.read_contents().await?;
+        let data: Vec<Process> = serde_json::from_slice(&contents)?;
+        Ok((to_map(data), content_version(&contents)))
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        let contents = self.read_contents().await?;
+        Ok(content_version(&contents))
+    }
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError> {
+        let _guard = self.write_lock.lock().await;
+        let current = self.read_contents().await?;
+        if content_version(&current) != version {
+            return Err(StoreError::Conflict(version.to_string()));
+        }
+        // A document edited by hand, or saved before versions were kept, is
+        // retained first so it can still be rolled back to.
+        if !self.version_ids().await?.last().is_some_and(|id| id.ends_with(version)) {
+            self.retain(&current).await?;
+        }
+        let contents = serde_json::to_vec_pretty(&to_list(data))?;
+        self.retain(&contents).await?;
+        // Write to a sibling file and rename so readers never see a partial document.
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, &contents).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(content_version(&contents))
+    }
+
+    async fn append_audit(&self, entries: &[AuditEntry]) -> Result<(), StoreError> {
+        use tokio::io::AsyncWriteExt;
+        tokio::fs::create_dir_all(&self.audit_dir).await?;
+        for (day, entries) in group_by_day(entries) {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.audit_dir.join(format!("{}.jsonl", day)))
+                .await?;
+            file.write_all(&audit::to_jsonl(&entries)?).await?;
+        }
+        Ok(())
+    }
+
+    async fn load_audit(&self, process_name: &str, days: &DayRange) -> Result<Vec<AuditEntry>, StoreError> {
+        let mut files = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.audit_dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            let day = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            if path.extension().is_some_and(|ext| ext == "jsonl") && days.contains(day) {
+                files.push(path);
+            }
+        }
+        // Daily file names sort chronologically.
+        files.sort();
+        let mut entries = Vec::new();
+        for file in files {
+            entries.extend(audit::from_jsonl(&tokio::fs::read(file).await?, process_name)?);
+        }
+        Ok(entries)
+    }
+
+    async fn list_versions(&self) -> Result<Vec<DocumentVersion>, StoreError> {
+        let current = self.current_version().await?;
+        let mut found_current = false;
+        let mut versions = Vec::new();
+        for version_id in self.version_ids().await?.into_iter().rev() {
+            let Some((time, etag)) = version_id.split_once('-') else { continue };
+            let saved_at = NaiveDateTime::parse_from_str(time, VERSION_TIME_FORMAT)
+                .map(|t| t.and_utc().to_rfc3339_opts(SecondsFormat::Millis, true))
+                .unwrap_or_default();
+            // The same document can be saved again later; only the newest copy is current.
+            let is_current = !found_current && etag == current;
+            found_current |= is_current;
+            versions.push(DocumentVersion {
+                etag: etag.to_string(),
+                version_id,
+                saved_at,
+                current: is_current,
+            });
+        }
+        Ok(versions)
+    }
+
+    async fn load_version(&self, version_id: &str) -> Result<HashMap<String, Process>, StoreError> {
+        // The ID becomes a file name, so it must not be able to point anywhere else.
+        if version_id.is_empty() || !version_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-') {
+            return Err(StoreError::VersionNotFound(version_id.to_string()));
+        }
+        let contents = match tokio::fs::read(self.versions_dir.join(format!("{}.json", version_id))).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(StoreError::VersionNotFound(version_id.to_string())),
+            Err(e) => return Err(e.into()),
+        };
+        let data: Vec<Process> = serde_json::from_slice(&contents)?;
+        Ok(to_map(data))
+    }
+
+    fn describe(&self) -> String {
+        format!("file://{}", self.path.display())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    etag: String,
+    saved_at: u64,
+    processes: Vec<Process>,
+}
+
+/// A local copy of the last document read from or written to the store, so
+/// the service can still start when the store is unreachable.
+pub struct LastKnownGood {
+    path: PathBuf,
+}
+
+impl LastKnownGood {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        LastKnownGood { path: path.into() }
+    }
+
+    pub async fn save(&self, processes: &HashMap<String, Process>, etag: &str) -> Result<(), StoreError> {
+        let snapshot = SnapshotFile {
+            etag: etag.to_string(),
+            saved_at: crate::cache::get_current_time(),
+            processes: to_list(processes),
+        };
+        let contents = serde_json::to_vec_pretty(&snapshot)?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, &contents).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+
+    /// The saved processes, their version and when they were saved.
+    pub async fn load(&self) -> Result<(HashMap<String, Process>, String, u64), StoreError> {
+        let contents = tokio::fs::read(&self.path).await?;
+        let snapshot: SnapshotFile = serde_json::from_slice(&contents)?;
+        Ok((to_map(snapshot.processes), snapshot.etag, snapshot.saved_at))
+    }
+
+    pub fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+/// Keeps the document in memory only; used for local runs and tests.
+pub struct MemoryStore {
+    state: Mutex<(HashMap<String, Process>, u64)>,
+    audit: Mutex<Vec<AuditEntry>>,
+    // Every saved document, oldest first.
+    versions: Mutex<Vec<MemoryVersion>>,
+}
+
+struct MemoryVersion {
+    version: u64,
+    saved_at: String,
+    processes: HashMap<String, Process>,
+}
+
+impl MemoryStore {
+    pub fn new(processes: HashMap<String, Process>) -> Self {
+        MemoryStore {
+            versions: Mutex::new(vec![MemoryVersion { version: 1, saved_at: now_rfc3339(), processes: processes.clone() }]),
+            state: Mutex::new((processes, 1)),
+            audit: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+fn now_rfc3339() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[async_trait]
+impl ProcessStore for MemoryStore {
+    async fn load(&self) -> Result<(HashMap<String, Process>, String), StoreError> {
+        let state = self.state.lock().unwrap();
+        Ok((state.0.clone(), state.1.to_string()))
+    }
+
+    async fn current_version(&self) -> Result<String, StoreError> {
+        Ok(self.state.lock().unwrap().1.to_string())
+    }
+
+    async fn save(&self, data: &HashMap<String, Process>, version: &str) -> Result<String, StoreError> {
+        let mut state = self.state.lock().unwrap();
+        if state.1.to_string() != version {
+            return Err(StoreError::Conflict(version.to_string()));
+        }
+        state.0 = data.clone();
+        state.1 += 1;
+        self.versions.lock().unwrap().push(MemoryVersion { version: state.1, saved_at: now_rfc3339(), processes: data.clone() });
+        Ok(state.1.to_string())
+    }
+
+    async fn append_audit(&self, entries: &[AuditEntry]) -> Result<(), StoreError> {
+        self.audit.lock().unwrap().extend_from_slice(entries);
+        Ok(())
+    }
+
+    async fn load_audit(&self, process_name: &str, days: &DayRange) -> Result<Vec<AuditEntry>, StoreError> {
+        Ok(self.audit.lock().unwrap().iter().filter(|e| e.process_name == process_name && days.contains(e.day())).cloned().collect())
+    }
+
+    async fn list_versions(&self) -> Result<Vec<DocumentVersion>, StoreError> {
+        let current = self.state.lock().unwrap().1;
+        Ok(self.versions.lock().unwrap().iter().rev()
+            .map(|v| DocumentVersion {
+                version_id: v.version.to_string(),
+                etag: v.version.to_string(),
+                saved_at: v.saved_at.clone(),
+                current: v.version == current,
+            })
+            .collect())
+    }
+
+    async fn load_version(&self, version_id: &str) -> Result<HashMap<String, Process>, StoreError> {
+        self.versions.lock().unwrap().iter()
+            .find(|v| v.version.to_string() == version_id)
+            .map(|v| v.processes.clone())
+            .ok_or_else(|| StoreError::VersionNotFound(version_id.to_string()))
+    }
+
+    fn describe(&self) -> String {
+        "memory://".to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_memory_store_rejects_stale_version() {
+    let store = MemoryStore::new(HashMap::new());
+    let (mut data, version) = store.load().await.unwrap();
+    data.insert("process1".to_string(), crate::cache::create_process("process1", true, None));
+    let new_version = store.save(&data, &version).await.unwrap();
+    assert_ne!(new_version, version);
+    assert!(matches!(store.save(&data, &version).await, Err(StoreError::Conflict(_))));
+    assert_eq!(store.load().await.unwrap().0.len(), 1);
+}
+
+#[tokio::test]
+async fn test_local_store_round_trip() {
+    let dir = std::env::temp_dir().join(format!("local-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("processes.json");
+    let store = LocalStore::new(path.to_str().unwrap());
+    let (mut data, version) = store.load().await.unwrap();
+    assert!(data.is_empty());
+    data.insert("process1".to_string(), crate::cache::create_process("process1", false, Some(vec!["dmi".to_string()])));
+    let new_version = store.save(&data, &version).await.unwrap();
+    let (loaded, loaded_version) = store.load().await.unwrap();
+    assert_eq!(loaded_version, new_version);
+    assert!(!loaded["process1"].run);
+    assert!(matches!(store.save(&data, &version).await, Err(StoreError::Conflict(_))));
+
+    // The empty document it replaced was retained too.
+    let versions = store.list_versions().await.unwrap();
+    assert_eq!(versions.len(), 2);
+    assert!(versions[0].current && versions[0].etag == new_version);
+    assert!(store.load_version(&versions[1].version_id).await.unwrap().is_empty());
+    assert!(matches!(store.load_version("../processes").await, Err(StoreError::VersionNotFound(_))));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_documents_side_by_side_are_isolated() {
+    let dir = std::env::temp_dir().join(format!("local-store-ns-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dev = LocalStore::new(dir.join("dev.json").to_str().unwrap());
+    let prod = LocalStore::new(dir.join("prod.json").to_str().unwrap());
+    let ctx = crate::audit::RequestContext::new("alice", "req-1");
+    for store in [&dev, &prod] {
+        let (previous, version) = store.load().await.unwrap();
+        let mut data = previous.clone();
+        data.insert("process1".to_string(), crate::cache::create_process("process1", store.path.ends_with("prod.json"), None));
+        store.save(&data, &version).await.unwrap();
+        store.append_audit(&audit::diff_entries(&previous, &data, &ctx)).await.unwrap();
+    }
+
+    for (store, run) in [(&dev, false), (&prod, true)] {
+        let history = store.load_audit("process1", &DayRange::default()).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].new.as_ref().unwrap().run, run);
+        let versions = store.list_versions().await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(store.load_version(&versions[0].version_id).await.unwrap()["process1"].run, run);
+    }
+    let today = Utc::now().date_naive();
+    assert_eq!(dev.load_audit("process1", &DayRange { since: Some(today), until: Some(today) }).await.unwrap().len(), 1);
+    assert!(dev.load_audit("process1", &DayRange { since: today.succ_opt(), until: None }).await.unwrap().is_empty());
+    assert_eq!(S3Store::new(Arc::new(Client::from_conf(aws_sdk_s3::Config::builder().behavior_version_latest().build())), "s3://bucket/API_CONTROL/dev.json")
+        .unwrap().audit_prefix, "API_CONTROL/audit/dev/");
+    std::fs::remove_dir_all(dir).unwrap();
+}
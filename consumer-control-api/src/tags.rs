@@ -0,0 +1,85 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use consumer_control_client::validation::{FieldError, MAX_TAGS};
+
+use crate::cache::{self, Process};
+use crate::error::ControlApiError;
+use crate::ProcessPatchInput;
+
+pub use consumer_control_client::model::TagSummary;
+
+/// The tags of `process` without `remove` and with `add`, or None when
+/// that is what it already carries.
+fn changed_tags(process: &Process, add: &[String], remove: &[String]) -> Option<Vec<String>> {
+    let current = process.tags.clone().unwrap_or_default();
+    let mut tags: Vec<String> = current.iter().filter(|t| !remove.contains(t)).cloned().collect();
+    for tag in add {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    (tags != current).then_some(tags)
+}
+
+/// Removes `remove` from and adds `add` to the tags of the processes in
+/// `names`, returning them. Nothing is changed when any of them would end up
+/// with more than `MAX_TAGS` tags.
+pub fn apply(all_processes: &mut HashMap<String, Process>, names: &[String], add: &[String], remove: &[String]) -> Result<Vec<Process>, ControlApiError> {
+    let mut changes = Vec::new();
+    for name in names {
+        if let Some(tags) = all_processes.get(name).and_then(|p| changed_tags(p, add, remove)) {
+            if tags.len() > MAX_TAGS {
+                return Err(ControlApiError::InvalidFields(vec![
+                    FieldError::new("add", format!("would give {} more than {} tags", name, MAX_TAGS)),
+                ]));
+            }
+            changes.push((name.clone(), tags));
+        }
+    }
+    for (name, tags) in changes {
+        let process = all_processes.get_mut(&name).unwrap();
+        cache::update_process_partial(process, &ProcessPatchInput { name, tags: Some(tags), ..Default::default() });
+    }
+    Ok(names.iter().filter_map(|name| all_processes.get(name).cloned()).collect())
+}
+
+/// Every tag carried by `processes`, by tag, counting each process once.
+pub fn summarize(processes: &[Process]) -> Vec<TagSummary> {
+    let mut summaries: BTreeMap<&str, TagSummary> = BTreeMap::new();
+    for process in processes {
+        let tags: BTreeSet<&String> = process.tags.iter().flatten().collect();
+        for tag in tags {
+            let summary = summaries.entry(tag).or_insert_with(|| TagSummary { tag: tag.clone(), count: 0, running: 0, stopped: 0 });
+            summary.count += 1;
+            if process.run {
+                summary.running += 1;
+            } else {
+                summary.stopped += 1;
+            }
+        }
+    }
+    summaries.into_values().collect()
+}
+
+#[test]
+fn test_apply_and_summarize() {
+    let tagged = |name: &str, run: bool, tags: &[&str]| cache::create_process(name, run, Some(tags.iter().map(|t| t.to_string()).collect()));
+    let mut processes = HashMap::from([
+        ("p1".to_string(), tagged("p1", true, &["es", "dmi"])),
+        ("p2".to_string(), tagged("p2", false, &["es"])),
+    ]);
+    let names = vec!["p1".to_string(), "p2".to_string()];
+    let updated = apply(&mut processes, &names, &["v4".to_string()], &["dmi".to_string()]).unwrap();
+    assert_eq!(updated[0].tags, Some(vec!["es".to_string(), "v4".to_string()]));
+    assert_eq!(updated[1].tags, Some(vec!["es".to_string(), "v4".to_string()]));
+
+    let many: Vec<String> = (0..MAX_TAGS).map(|i| format!("t{}", i)).collect();
+    assert!(apply(&mut processes, &names, &many, &[]).is_err());
+    assert_eq!(processes["p1"].tags.as_ref().unwrap().len(), 2);
+
+    let summaries = summarize(&processes.into_values().collect::<Vec<_>>());
+    assert_eq!(summaries, vec![
+        TagSummary { tag: "es".to_string(), count: 2, running: 1, stopped: 1 },
+        TagSummary { tag: "v4".to_string(), count: 2, running: 1, stopped: 1 },
+    ]);
+}
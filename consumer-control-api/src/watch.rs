@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::Stream;
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::cache::ProcessView;
+use crate::error::ControlApiError;
+use crate::query::Filter;
+use crate::{selection_filter, ProcessQuery};
+
+pub use consumer_control_client::model::{RunState, WatchParams, WatchSnapshot};
+
+/// How often watchers re-check the cache even without a change
+/// notification, so scheduled transitions are picked up within a second.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often an idle event stream sends a comment to keep proxies from closing it.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+pub const DEFAULT_WAIT_SECS: u64 = 30;
+pub const MAX_WAIT_SECS: u64 = 60;
+
+/// The processes a watch request selects.
+pub fn filter(params: &WatchParams) -> Result<Filter, ControlApiError> {
+    selection_filter(&ProcessQuery {
+        name_patterns: params.name_patterns.clone(),
+        tags: params.tags.clone(),
+        reason: None,
+    })
+}
+
+fn snapshot(view: &ProcessView, filter: &Filter) -> Result<WatchSnapshot, ControlApiError> {
+    let mut processes: Vec<RunState> = view.filter_processes(filter, chrono::Utc::now())?
+        .into_iter()
+        .map(|p| RunState { name: p.name, run: p.run })
+        .collect();
+    processes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(WatchSnapshot {
+        version: version(&processes),
+        processes,
+    })
+}
+
+/// Stable across builds and replicas, so a watcher can reconnect anywhere.
+fn version(processes: &[RunState]) -> String {
+    let digest = Sha256::digest(serde_json::to_vec(processes).unwrap_or_default());
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Waits for a change notification or the recheck interval, whichever comes first.
+async fn next_check(changes: &mut watch::Receiver<u64>) {
+    tokio::select! {
+        _ = changes.changed() => {},
+        _ = tokio::time::sleep(RECHECK_INTERVAL) => {},
+    }
+}
+
+/// Returns the first snapshot whose version differs from `version`, or
+/// `None` when nothing changed within `wait`.
+pub async fn wait_for_change(view: Arc<ProcessView>, filter: Filter, version: Option<String>, wait: Duration) -> Result<Option<WatchSnapshot>, ControlApiError> {
+    let mut changes = view.subscribe();
+    let deadline = Instant::now() + wait;
+    loop {
+        let current = snapshot(&view, &filter)?;
+        if version.as_ref() != Some(&current.version) {
+            return Ok(Some(current));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        tokio::select! {
+            _ = next_check(&mut changes) => {},
+            _ = tokio::time::sleep_until(deadline) => {},
+        }
+    }
+}
+
+/// A Server-Sent Events stream with one `processes` event per change,
+/// starting with the current state unless it still matches `version`.
+/// When the processes become too stale to serve, one `error` event is sent,
+/// and the next `processes` event tells they can be served again.
+pub fn event_stream(view: Arc<ProcessView>, filter: Filter, version: Option<String>) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let changes = view.subscribe();
+    futures::stream::unfold((version, changes, Instant::now(), false), move |(mut version, mut changes, last_sent, stale)| {
+        let view = view.clone();
+        let filter = filter.clone();
+        async move {
+            loop {
+                match snapshot(&view, &filter) {
+                    Ok(current) if version.as_ref() != Some(&current.version) => {
+                        let data = serde_json::to_string(&current).unwrap_or_default();
+                        let event = format!("event: processes\nid: {}\ndata: {}\n\n", current.version, data);
+                        version = Some(current.version);
+                        return Some((Ok(Bytes::from(event)), (version, changes, Instant::now(), false)));
+                    },
+                    Ok(_) => {},
+                    Err(e) if !stale => {
+                        // Forget the version so the next good snapshot is sent again.
+                        let event = format!("event: error\ndata: {}\n\n", e);
+                        return Some((Ok(Bytes::from(event)), (None, changes, Instant::now(), true)));
+                    },
+                    // Already reported; wait for the processes to be refreshed.
+                    Err(_) => {},
+                }
+                if last_sent.elapsed() >= KEEPALIVE_INTERVAL {
+                    return Some((Ok(Bytes::from_static(b": keepalive\n\n")), (version, changes, Instant::now(), stale)));
+                }
+                next_check(&mut changes).await;
+            }
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_wait_for_change_wakes_on_write() {
+    use crate::audit::RequestContext;
+    use crate::cache::create_process;
+
+    let store = Arc::new(crate::store::MemoryStore::new(std::collections::HashMap::new()));
+    let state = Arc::new(tokio::sync::Mutex::new(crate::MyCache::new(store, 0, None).await.unwrap()));
+    let view = state.lock().await.view();
+    let ctx = RequestContext::new("alice", "req-1");
+    state.lock().await.add_process(create_process("process1", true, None), &ctx).await.unwrap();
+    let filter = Filter::name_patterns(&["process*".to_string()]).unwrap();
+
+    let first = wait_for_change(view.clone(), filter.clone(), None, Duration::ZERO).await.unwrap().unwrap();
+    // Versions must not change with the build, or watchers reconnecting after a deploy see a change.
+    assert_eq!(first.version, "6406bd446de2655e");
+    assert!(wait_for_change(view.clone(), filter.clone(), Some(first.version.clone()), Duration::ZERO).await.unwrap().is_none());
+
+    let writer = state.clone();
+    tokio::spawn(async move {
+        writer.lock().await.update_process_partial(&crate::cache::stop_input("process1"), &ctx).await.unwrap();
+    });
+    let changed = wait_for_change(view, filter, Some(first.version), Duration::from_secs(5)).await.unwrap().unwrap();
+    assert!(!changed.processes[0].run);
+}
@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::ClientError;
+use crate::model::{
+    AuditEntry, BulkTagsInput, DocumentVersion, GenericErrorResponse, GroupReport, Process, ProcessChange, ProcessInput,
+    ProcessMessage, ProcessPatchInput, ProcessQuery, ProcessQueryParams, ProcessRename, ProcessTagsInput, TagSummary,
+    WatchParams, WatchSnapshot,
+};
+
+/// Header carrying a static API key.
+const API_KEY_HEADER: &str = "x-api-key";
+/// Header naming the caller in the audit trail when the API runs without authentication.
+const CALLER_HEADER: &str = "x-caller";
+/// Set on a page of `GET /processes` when more processes follow.
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+/// Seconds the API waits for a change when a watch gives no timeout.
+const DEFAULT_WATCH_SECS: u64 = 30;
+
+/// What `should_run` answers when the API cannot be reached and the process
+/// has not been seen since the client was built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// Keep running, so an outage of the control API stops nothing.
+    #[default]
+    FailOpen,
+    /// Stop, so nothing runs that an operator may have meant to stop.
+    FailClosed,
+}
+
+/// Which failed attempts are sent again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Retry {
+    /// Requests that can be repeated without changing the outcome.
+    Always,
+    /// Requests that must not be applied twice; only retried when the API
+    /// surely did not act on them.
+    IfUnsent,
+    Never,
+}
+
+pub struct ClientBuilder {
+    base_url: String,
+    namespace: Option<String>,
+    api_key: Option<String>,
+    bearer_token: Option<String>,
+    caller: Option<String>,
+    timeout: Duration,
+    connect_timeout: Duration,
+    max_retries: u32,
+    retry_backoff: Duration,
+    failure_policy: FailurePolicy,
+}
+
+impl ClientBuilder {
+    pub fn new(base_url: &str) -> Self {
+        ClientBuilder {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            namespace: None,
+            api_key: None,
+            bearer_token: None,
+            caller: None,
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(3),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+            failure_policy: FailurePolicy::default(),
+        }
+    }
+
+    /// Sends the process requests to `/ns/{namespace}` instead of the default namespace.
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.bearer_token = Some(token.to_string());
+        self
+    }
+
+    /// Recorded as the caller of every change when the API runs without authentication.
+    pub fn caller(mut self, caller: &str) -> Self {
+        self.caller = Some(caller.to_string());
+        self
+    }
+
+    /// Longest a request may take, not counting the wait of a watch.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Attempts after the first one; 0 disables retries.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for every one after it.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+
+    pub fn failure_policy(mut self, policy: FailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<ControlClient, ClientError> {
+        if !self.base_url.starts_with("http://") && !self.base_url.starts_with("https://") {
+            return Err(ClientError::Config(format!("base URL '{}' must start with http:// or https://", self.base_url)));
+        }
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = &self.api_key {
+            headers.insert(API_KEY_HEADER, header_value(api_key)?);
+        }
+        if let Some(token) = &self.bearer_token {
+            headers.insert(AUTHORIZATION, header_value(&format!("Bearer {}", token))?);
+        }
+        if let Some(caller) = &self.caller {
+            headers.insert(CALLER_HEADER, header_value(caller)?);
+        }
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(self.timeout)
+            .connect_timeout(self.connect_timeout)
+            .build()?;
+        let process_url = match &self.namespace {
+            Some(namespace) => format!("{}/ns/{}", self.base_url, namespace),
+            None => self.base_url.clone(),
+        };
+        Ok(ControlClient {
+            http,
+            base_url: self.base_url,
+            process_url,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            retry_backoff: self.retry_backoff,
+            failure_policy: self.failure_policy,
+            last_known: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, ClientError> {
+    HeaderValue::from_str(value).map_err(|e| ClientError::Config(format!("invalid header value: {}", e)))
+}
+
+/// One page of `GET /processes`.
+#[derive(Debug, Clone)]
+pub struct ProcessPage<T> {
+    pub processes: Vec<T>,
+    /// Pass back as `cursor` to get the next page; `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// A client for one namespace of the control API. Cloning is cheap and
+/// clones share their connections and last-known run flags.
+#[derive(Clone)]
+pub struct ControlClient {
+    http: reqwest::Client,
+    base_url: String,
+    process_url: String,
+    timeout: Duration,
+    max_retries: u32,
+    retry_backoff: Duration,
+    failure_policy: FailurePolicy,
+    // The run flag of every process `should_run` got an answer for.
+    last_known: Arc<Mutex<HashMap<String, bool>>>,
+}
+
+impl ControlClient {
+    pub fn builder(base_url: &str) -> ClientBuilder {
+        ClientBuilder::new(base_url)
+    }
+
+    /// Whether `process_name` should be running. Never fails: when the API
+    /// can't answer, the last answer it gave is used, and without one the
+    /// failure policy decides. Processes the API doesn't know run.
+    pub async fn should_run(&self, process_name: &str) -> bool {
+        self.try_should_run(process_name).await.unwrap_or_else(|_| self.fallback(process_name))
+    }
+
+    /// `should_run`, falling back only while the API can't be reached, so
+    /// an error that won't go away, e.g. an unknown namespace, is surfaced.
+    pub async fn try_should_run(&self, process_name: &str) -> Result<bool, ClientError> {
+        match self.get_process(process_name).await {
+            Ok(process) => {
+                let run = process.is_none_or(|p| p.run);
+                self.last_known.lock().unwrap().insert(process_name.to_string(), run);
+                Ok(run)
+            },
+            Err(e) if e.is_transient() => Ok(self.fallback(process_name)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn fallback(&self, process_name: &str) -> bool {
+        self.last_known(process_name).unwrap_or(self.failure_policy == FailurePolicy::FailOpen)
+    }
+
+    /// The run flag `should_run` last got from the API for `process_name`.
+    pub fn last_known(&self, process_name: &str) -> Option<bool> {
+        self.last_known.lock().unwrap().get(process_name).copied()
+    }
+
+    /// `GET /process`, with `run` resolved from the schedule; `None` when the
+    /// process doesn't exist. Any other 404, like `namespace_not_found`, is an error.
+    pub async fn get_process(&self, process_name: &str) -> Result<Option<Process>, ClientError> {
+        let url = self.url("/process");
+        let response = self.send(Retry::Always, || self.http.get(&url).query(&[("process_name", process_name)])).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            let body = response.text().await?;
+            if is_unknown_process(&body, process_name) {
+                return Ok(None);
+            }
+            return Err(api_error(StatusCode::NOT_FOUND, body));
+        }
+        json(response).await.map(Some)
+    }
+
+    /// `POST /process`, returning the process as stored.
+    pub async fn add_process(&self, input: &ProcessInput) -> Result<Process, ClientError> {
+        let url = self.url("/process");
+        json(self.send(Retry::IfUnsent, || self.http.post(&url).json(input)).await?).await
+    }
+
+    /// `PUT /process`, replacing the whole process.
+    pub async fn update_process(&self, input: &ProcessInput) -> Result<(), ClientError> {
+        let url = self.url("/process");
+        empty(self.send(Retry::Always, || self.http.put(&url).json(input)).await?).await
+    }
+
+    /// `PATCH /process`, changing only the fields given.
+    pub async fn patch_process(&self, input: &ProcessPatchInput) -> Result<(), ClientError> {
+        let url = self.url("/process");
+        empty(self.send(Retry::Always, || self.http.patch(&url).json(input)).await?).await
+    }
+
+    pub async fn delete_process(&self, process_name: &str) -> Result<(), ClientError> {
+        let url = self.url("/process");
+        empty(self.send(Retry::IfUnsent, || self.http.delete(&url).query(&[("process_name", process_name)])).await?).await
+    }
+
+    /// `POST /process/rename`, returning the process under its new name.
+    pub async fn rename_process(&self, name: &str, new_name: &str) -> Result<Process, ClientError> {
+        let url = self.url("/process/rename");
+        let rename = ProcessRename { name: name.to_string(), new_name: new_name.to_string() };
+        json(self.send(Retry::IfUnsent, || self.http.post(&url).json(&rename)).await?).await
+    }
+
+    /// `PATCH /process/tags`, returning the process with its new tags.
+    pub async fn change_tags(&self, input: &ProcessTagsInput) -> Result<Process, ClientError> {
+        let url = self.url("/process/tags");
+        json(self.send(Retry::Always, || self.http.patch(&url).json(input)).await?).await
+    }
+
+    /// `GET /process/history`, oldest change first.
+    pub async fn history(&self, process_name: &str) -> Result<Vec<AuditEntry>, ClientError> {
+        let url = self.url("/process/history");
+        json(self.send(Retry::Always, || self.http.get(&url).query(&[("process_name", process_name)])).await?).await
+    }
+
+    /// Long-polls `GET /process/watch`; `None` when nothing changed from
+    /// `params.version` within `params.timeout`.
+    pub async fn watch(&self, params: &WatchParams) -> Result<Option<WatchSnapshot>, ClientError> {
+        let url = self.url("/process/watch");
+        let wait = Duration::from_secs(params.timeout.unwrap_or(DEFAULT_WATCH_SECS));
+        let query = params.to_query_pairs();
+        let response = self.send(Retry::Always, || self.http.get(&url).query(&query).timeout(self.timeout + wait)).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        json(response).await.map(Some)
+    }
+
+    /// `GET /processes`. `params.fields` must be unset, since a projection
+    /// isn't a whole `Process`; use `list_process_fields` for that.
+    pub async fn list_processes(&self, params: &ProcessQueryParams) -> Result<ProcessPage<Process>, ClientError> {
+        if params.fields.is_some() {
+            return Err(ClientError::Config("list_processes returns whole processes; use list_process_fields with 'fields'".to_string()));
+        }
+        self.list(params).await
+    }
+
+    /// `GET /processes` with a projection, each process as a JSON object of the fields asked for.
+    pub async fn list_process_fields(&self, params: &ProcessQueryParams) -> Result<ProcessPage<serde_json::Value>, ClientError> {
+        self.list(params).await
+    }
+
+    async fn list<T: DeserializeOwned>(&self, params: &ProcessQueryParams) -> Result<ProcessPage<T>, ClientError> {
+        let url = self.url("/processes");
+        let query = params.to_query_pairs();
+        let response = self.send(Retry::Always, || self.http.get(&url).query(&query)).await?;
+        let next_cursor = response.headers().get(NEXT_CURSOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        Ok(ProcessPage { processes: json(response).await?, next_cursor })
+    }
+
+    /// `PUT /processes`: updates the processes given and adds the missing ones.
+    pub async fn merge_processes(&self, inputs: &[ProcessPatchInput]) -> Result<Vec<ProcessMessage>, ClientError> {
+        let url = self.url("/processes");
+        json(self.send(Retry::Always, || self.http.put(&url).json(inputs)).await?).await
+    }
+
+    /// What `merge_processes` would change, without saving anything.
+    pub async fn preview_merge_processes(&self, inputs: &[ProcessPatchInput]) -> Result<Vec<ProcessChange>, ClientError> {
+        let url = self.url("/processes");
+        json(self.send(Retry::Always, || self.http.put(&url).query(&[("dry_run", "true")]).json(inputs)).await?).await
+    }
+
+    /// `PATCH /processes/{start|stop}`, returning the processes selected.
+    pub async fn control_processes(&self, run: bool, selection: &ProcessQuery) -> Result<Vec<Process>, ClientError> {
+        let url = self.url(&format!("/processes/{}", action(run)));
+        json(self.send(Retry::Always, || self.http.patch(&url).json(selection)).await?).await
+    }
+
+    /// What `control_processes` would change, without saving anything.
+    pub async fn preview_control_processes(&self, run: bool, selection: &ProcessQuery) -> Result<Vec<ProcessChange>, ClientError> {
+        let url = self.url(&format!("/processes/{}", action(run)));
+        json(self.send(Retry::Always, || self.http.patch(&url).query(&[("dry_run", "true")]).json(selection)).await?).await
+    }
+
+    /// `PATCH /processes/tags`, returning the processes selected.
+    pub async fn change_tags_matching(&self, input: &BulkTagsInput) -> Result<Vec<Process>, ClientError> {
+        let url = self.url("/processes/tags");
+        json(self.send(Retry::Always, || self.http.patch(&url).json(input)).await?).await
+    }
+
+    /// What `change_tags_matching` would change, without saving anything.
+    pub async fn preview_change_tags_matching(&self, input: &BulkTagsInput) -> Result<Vec<ProcessChange>, ClientError> {
+        let url = self.url("/processes/tags");
+        json(self.send(Retry::Always, || self.http.patch(&url).query(&[("dry_run", "true")]).json(input)).await?).await
+    }
+
+    /// `GET /tags`, by tag.
+    pub async fn list_tags(&self) -> Result<Vec<TagSummary>, ClientError> {
+        let url = self.url("/tags");
+        json(self.send(Retry::Always, || self.http.get(&url)).await?).await
+    }
+
+    /// `DELETE /processes`, returning the processes deleted. `confirm` must
+    /// be the number of processes selected, as `preview_delete_processes`
+    /// reports, or nothing is deleted.
+    pub async fn delete_processes(&self, selection: &ProcessQuery, confirm: usize) -> Result<Vec<Process>, ClientError> {
+        let url = self.url("/processes");
+        json(self.send(Retry::IfUnsent, || self.http.delete(&url).query(&[("confirm", confirm)]).json(selection)).await?).await
+    }
+
+    /// What `delete_processes` would delete, without saving anything.
+    pub async fn preview_delete_processes(&self, selection: &ProcessQuery) -> Result<Vec<ProcessChange>, ClientError> {
+        let url = self.url("/processes");
+        json(self.send(Retry::Always, || self.http.delete(&url).query(&[("dry_run", "true")]).json(selection)).await?).await
+    }
+
+    /// `PATCH /groups/{group}/{start|stop}`. A report with `complete` unset
+    /// means a step failed and the group was left partly changed. The API
+    /// pauses `interval` between steps before answering, so the client's
+    /// timeout has to allow for every pause.
+    pub async fn control_group(&self, group: &str, run: bool, interval: Duration, reason: Option<&str>) -> Result<GroupReport, ClientError> {
+        let url = self.url(&format!("/groups/{}/{}", group, action(run)));
+        let mut query = vec![("interval_secs", interval.as_secs().to_string())];
+        query.extend(reason.map(|r| ("reason", r.to_string())));
+        json(self.send(Retry::IfUnsent, || self.http.patch(&url).query(&query)).await?).await
+    }
+
+    /// The steps `control_group` would take, without changing anything.
+    pub async fn preview_group(&self, group: &str, run: bool) -> Result<GroupReport, ClientError> {
+        let url = self.url(&format!("/groups/{}/{}", group, action(run)));
+        json(self.send(Retry::Always, || self.http.patch(&url).query(&[("dry_run", "true")])).await?).await
+    }
+
+    /// `GET /processes/versions`.
+    pub async fn list_versions(&self) -> Result<Vec<DocumentVersion>, ClientError> {
+        let url = self.url("/processes/versions");
+        json(self.send(Retry::Always, || self.http.get(&url)).await?).await
+    }
+
+    /// Every process that differs between version `from` and version `to`,
+    /// or the processes currently served when `to` is `None`.
+    pub async fn diff_versions(&self, from: &str, to: Option<&str>) -> Result<Vec<ProcessChange>, ClientError> {
+        let url = self.url("/processes/diff");
+        let mut query = vec![("from", from)];
+        query.extend(to.map(|to| ("to", to)));
+        json(self.send(Retry::Always, || self.http.get(&url).query(&query)).await?).await
+    }
+
+    /// `POST /processes/rollback`, returning what it changed.
+    pub async fn rollback(&self, version_id: &str) -> Result<Vec<ProcessChange>, ClientError> {
+        let url = self.url("/processes/rollback");
+        json(self.send(Retry::IfUnsent, || self.http.post(&url).query(&[("version", version_id)])).await?).await
+    }
+
+    /// `GET /healthz`.
+    pub async fn health(&self) -> Result<serde_json::Value, ClientError> {
+        let url = format!("{}/healthz", self.base_url);
+        json(self.send(Retry::Always, || self.http.get(&url)).await?).await
+    }
+
+    /// `GET /readyz`: whether the API is ready, and the cache details it reported.
+    pub async fn readiness(&self) -> Result<(bool, serde_json::Value), ClientError> {
+        let url = format!("{}/readyz", self.base_url);
+        let response = self.send(Retry::Never, || self.http.get(&url)).await?;
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            return Ok((false, response.json().await?));
+        }
+        Ok((true, json(response).await?))
+    }
+
+    /// `GET /metrics`, in the Prometheus text format.
+    pub async fn metrics(&self) -> Result<String, ClientError> {
+        let url = format!("{}/metrics", self.base_url);
+        let response = check(self.send(Retry::Always, || self.http.get(&url).header(ACCEPT, "text/plain")).await?).await?;
+        Ok(response.text().await?)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.process_url, path)
+    }
+
+    /// Sends the request built by `request`, again after a transient failure
+    /// while `retry` allows it. Error statuses are returned as responses for
+    /// the caller to interpret.
+    async fn send<F>(&self, retry: Retry, request: F) -> Result<Response, ClientError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let retries_left = attempt <= self.max_retries;
+            match request().send().await {
+                Ok(response) if retries_left && is_retryable_status(retry, response.status()) => {},
+                Ok(response) => return Ok(response),
+                Err(e) if retries_left && is_retryable_error(retry, &e) => {},
+                Err(e) => return Err(e.into()),
+            }
+            tokio::time::sleep(retry_delay(self.retry_backoff, attempt)).await;
+        }
+    }
+}
+
+fn action(run: bool) -> &'static str {
+    if run { "start" } else { "stop" }
+}
+
+fn is_retryable_status(retry: Retry, status: StatusCode) -> bool {
+    match retry {
+        Retry::Always => matches!(status.as_u16(), 429 | 502 | 503 | 504),
+        // The API answers 503 when it could not save a change.
+        Retry::IfUnsent => status == StatusCode::SERVICE_UNAVAILABLE,
+        Retry::Never => false,
+    }
+}
+
+fn is_retryable_error(retry: Retry, error: &reqwest::Error) -> bool {
+    match retry {
+        Retry::Always => error.is_connect() || error.is_timeout() || error.is_request(),
+        Retry::IfUnsent => error.is_connect(),
+        Retry::Never => false,
+    }
+}
+
+/// Exponential backoff from `base` with up to 50% jitter, so consumers that
+/// lost the API at the same moment don't retry in lockstep.
+fn retry_delay(base: Duration, attempt: u32) -> Duration {
+    let delay = base.saturating_mul(1 << (attempt - 1).min(16));
+    let jitter_ns = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos() as u128 % (delay.as_nanos() / 2 + 1);
+    delay + Duration::from_nanos(jitter_ns as u64)
+}
+
+/// The response when its status is a success, or the error the API reported.
+async fn check(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if status.is_success() || status == StatusCode::NOT_MODIFIED {
+        return Ok(response);
+    }
+    let body = response.text().await?;
+    Err(api_error(status, body))
+}
+
+fn api_error(status: StatusCode, body: String) -> ClientError {
+    match serde_json::from_str::<GenericErrorResponse>(&body) {
+        Ok(error) => ClientError::Api(error),
+        Err(_) => ClientError::Unexpected { status: status.as_u16(), body },
+    }
+}
+
+/// The body `GET /process` answers a process that was never registered with.
+#[derive(Deserialize)]
+struct UnknownProcess {
+    name: String,
+}
+
+/// True when a 404 of `GET /process` means `process_name` doesn't exist,
+/// rather than that the namespace or the API itself wasn't found.
+fn is_unknown_process(body: &str, process_name: &str) -> bool {
+    serde_json::from_str::<UnknownProcess>(body).is_ok_and(|p| p.name == process_name)
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, ClientError> {
+    Ok(check(response).await?.json().await?)
+}
+
+async fn empty(response: Response) -> Result<(), ClientError> {
+    check(response).await.map(|_| ())
+}
+
+#[test]
+fn test_retry_delay_doubles_with_jitter() {
+    let base = Duration::from_millis(100);
+    for attempt in 1..=4 {
+        let expected = base * (1 << (attempt - 1));
+        let delay = retry_delay(base, attempt);
+        assert!(delay >= expected && delay <= expected * 3 / 2, "attempt {}: {:?}", attempt, delay);
+    }
+    assert!(retry_delay(base, 100) <= base * (1 << 16) * 3 / 2);
+}
+
+#[test]
+fn test_only_unknown_processes_are_none() {
+    assert!(is_unknown_process(r#"{"name":"process1","run":true}"#, "process1"));
+    let namespace = r#"{"code":404,"error":"namespace_not_found","message":"Namespace qa is not configured"}"#;
+    assert!(!is_unknown_process(namespace, "process1"));
+    assert_eq!(api_error(StatusCode::NOT_FOUND, namespace.to_string()).error_code(), Some("namespace_not_found"));
+    assert!(!is_unknown_process("<html>Not Found</html>", "process1"));
+}
+
+// tokio only has its macros as a dev-dependency.
+#[cfg(test)]
+#[tokio::test]
+async fn test_should_run_falls_back_when_unreachable() {
+    // Nothing listens on port 9, so every request fails to connect.
+    let client = |policy| ControlClient::builder("http://127.0.0.1:9").max_retries(0).failure_policy(policy).build().unwrap();
+
+    assert!(client(FailurePolicy::FailOpen).should_run("process1").await);
+    let closed = client(FailurePolicy::FailClosed);
+    assert!(!closed.should_run("process1").await);
+
+    closed.last_known.lock().unwrap().insert("process1".to_string(), true);
+    assert!(closed.should_run("process1").await);
+    assert_eq!(closed.last_known("process1"), Some(true));
+}
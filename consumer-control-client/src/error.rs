@@ -0,0 +1,47 @@
+use thiserror::Error;
+
+use crate::model::GenericErrorResponse;
+
+/// Every way a call to the control API can fail.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("invalid client configuration: {0}")]
+    Config(String),
+    #[error("request to the control API failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The API rejected the request; `error` holds its stable error code.
+    #[error("control API answered {}: {}", .0.error, .0.message)]
+    Api(GenericErrorResponse),
+    /// The API answered with a status and body it does not document.
+    #[error("control API answered {status}: {body}")]
+    Unexpected { status: u16, body: String },
+}
+
+impl ClientError {
+    /// The HTTP status the API answered with, if it answered at all.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Api(response) => Some(response.code as u16),
+            ClientError::Unexpected { status, .. } => Some(*status),
+            ClientError::Http(e) => e.status().map(|s| s.as_u16()),
+            ClientError::Config(_) => None,
+        }
+    }
+
+    /// The stable error code of a rejected request, e.g. `write_conflict`.
+    pub fn error_code(&self) -> Option<&str> {
+        match self {
+            ClientError::Api(response) => Some(&response.error),
+            _ => None,
+        }
+    }
+
+    /// True when the same request may succeed if sent again: the API could
+    /// not be reached, timed out, or reported its storage unavailable.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Http(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            _ => matches!(self.status(), Some(429 | 502 | 503 | 504)),
+        }
+    }
+}
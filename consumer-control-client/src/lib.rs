@@ -0,0 +1,20 @@
+//! Client for consumer-control-api, and the process models it shares with
+//! the server.
+//!
+//! Consumers mostly need `ControlClient::should_run`, which keeps answering
+//! from the last value it saw, or from the configured `FailurePolicy`, while
+//! the API cannot be reached.
+
+pub mod model;
+pub mod schedule;
+pub mod validation;
+
+#[cfg(feature = "client")]
+mod client;
+#[cfg(feature = "client")]
+mod error;
+
+#[cfg(feature = "client")]
+pub use client::{ClientBuilder, ControlClient, FailurePolicy, ProcessPage};
+#[cfg(feature = "client")]
+pub use error::ClientError;
@@ -0,0 +1,743 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{DateTime, Local, Utc};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::schedule::Schedule;
+use crate::validation::{self, check_name, check_tags, check_text, normalize_tags, FieldError, LABEL_KEY, LABEL_VALUE, LONG_TEXT, MAX_LABELS, NAME, SHORT_TEXT};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Process {
+  pub name: String,
+  pub run: bool,
+  pub tags: Option<Vec<String>>,
+  pub effective: String, // Store effective date/time as a string for simplicity
+  /// When `run` was last set explicitly, formatted like `effective`, which
+  /// also moves with every other change. Documents written before it existed
+  /// fall back to `effective`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub run_set_at: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub schedule: Option<Schedule>,
+  /// Dependency group the process is started and stopped with.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub group: Option<String>,
+  /// Members of the same group started before this process and stopped after it.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub depends_on: Option<Vec<String>>,
+  #[serde(flatten)]
+  pub metadata: Metadata,
+  /// Why `run` was last set, as given with that change.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+}
+
+/// Who a process belongs to and what it is, stored alongside it. Every
+/// field is optional, so documents written before it existed still load.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Metadata {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub owner: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub team: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub contact: Option<String>,
+  /// Free-form `key=value` pairs, unlike bare tags.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub labels: Option<BTreeMap<String, String>>,
+}
+
+impl Metadata {
+    pub fn validate(&self, errors: &mut Vec<FieldError>) {
+        check_text(&SHORT_TEXT, "owner", &self.owner, errors);
+        check_text(&SHORT_TEXT, "team", &self.team, errors);
+        check_text(&LONG_TEXT, "description", &self.description, errors);
+        check_text(&SHORT_TEXT, "contact", &self.contact, errors);
+        let labels = self.labels.iter().flatten();
+        if labels.clone().count() > MAX_LABELS {
+            errors.push(FieldError::new("labels", format!("must hold at most {} labels", MAX_LABELS)));
+        }
+        for (key, value) in labels {
+            LABEL_KEY.check("labels", key, errors);
+            LABEL_VALUE.check(&format!("labels.{}", key), value, errors);
+        }
+    }
+
+    /// Overwrites the fields set in `patch`; an empty value clears the field.
+    pub fn apply(&mut self, patch: &Metadata) {
+        let set = |field: &mut Option<String>, value: &Option<String>| {
+            if let Some(value) = value {
+                *field = Some(value.clone()).filter(|v| !v.is_empty());
+            }
+        };
+        set(&mut self.owner, &patch.owner);
+        set(&mut self.team, &patch.team);
+        set(&mut self.description, &patch.description);
+        set(&mut self.contact, &patch.contact);
+        if let Some(labels) = &patch.labels {
+            self.labels = Some(labels.clone()).filter(|l| !l.is_empty());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+}
+
+impl Process {
+    /// The process as it should behave at `now`, with `run` resolved from its schedule.
+    pub fn resolved(&self, now: DateTime<Utc>) -> Process {
+        let mut process = self.clone();
+        if let Some(schedule) = &self.schedule {
+            process.run = schedule.resolve(self.run, self.run_set_at.as_deref().unwrap_or(&self.effective), now);
+        }
+        process
+    }
+}
+
+pub fn create_process(name: &str, run: bool, tags: Option<Vec<String>>) -> Process {
+    let now = Local::now();
+    let effective = now.format("%Y-%m-%d %H:%M:%S").to_string();
+    Process {
+        name: name.to_string(),
+        run,
+        tags,
+        run_set_at: Some(effective.clone()),
+        effective,
+        schedule: None,
+        group: None,
+        depends_on: None,
+        metadata: Metadata::default(),
+        reason: None,
+    }
+}
+
+/// Sets the dependency fields given; an empty group or list removes them.
+pub fn apply_dependencies(process: &mut Process, group: &Option<String>, depends_on: &Option<Vec<String>>) {
+    if let Some(group) = group {
+        process.group = Some(group.clone()).filter(|g| !g.is_empty());
+    }
+    if let Some(depends_on) = depends_on {
+        process.depends_on = Some(depends_on.clone()).filter(|d| !d.is_empty());
+    }
+}
+
+/// Checks the dependency fields a caller sent for process `name`. Groups
+/// are named like processes; dependencies name existing ones.
+pub fn validate_dependencies(name: &str, group: &Option<String>, depends_on: &Option<Vec<String>>, errors: &mut Vec<FieldError>) {
+    if let Some(group) = group.as_deref().filter(|g| !g.is_empty()) {
+        NAME.check("group", group, errors);
+    }
+    for (i, dependency) in depends_on.iter().flatten().enumerate() {
+        let field = format!("depends_on[{}]", i);
+        if dependency == name {
+            errors.push(FieldError::new(field, format!("{} cannot depend on itself.", name)));
+        } else {
+            check_name(&field, dependency, false, errors);
+        }
+    }
+}
+
+fn validate_schedule(schedule: &Option<Schedule>, errors: &mut Vec<FieldError>) {
+    if let Some(Err(e)) = schedule.as_ref().map(Schedule::validate) {
+        errors.push(FieldError::new("schedule", e));
+    }
+}
+
+/// Body of `POST /process` and `PUT /process`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessInput {
+    pub name: String,
+    pub run: bool,
+    pub tags: Option<Vec<String>>,
+    pub schedule: Option<Schedule>,
+    pub group: Option<String>,
+    pub depends_on: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    pub reason: Option<String>,
+}
+
+impl ProcessInput {
+    /// Trims and deduplicates the tags; called before `validate`.
+    pub fn normalize(&mut self) {
+        normalize_tags(&mut self.tags);
+    }
+
+    /// Every field that breaks a rule, so the caller can fix them all at once,
+    /// for the process `name` already names.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        self.check(false)
+    }
+
+    /// As `validate`, for a process being created.
+    pub fn validate_new(&self) -> Result<(), Vec<FieldError>> {
+        self.check(true)
+    }
+
+    fn check(&self, new: bool) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, new, &mut errors);
+        check_tags("tags", &self.tags, &mut errors);
+        validate_schedule(&self.schedule, &mut errors);
+        validate_dependencies(&self.name, &self.group, &self.depends_on, &mut errors);
+        self.metadata.validate(&mut errors);
+        check_text(&LONG_TEXT, "reason", &self.reason, &mut errors);
+        validation::into_result(errors)
+    }
+
+    pub fn to_process(&self) -> Process {
+        let mut process = create_process(&self.name, self.run, self.tags.clone());
+        process.schedule = self.schedule.clone().filter(|s| !s.is_empty());
+        apply_dependencies(&mut process, &self.group, &self.depends_on);
+        process.metadata.apply(&self.metadata);
+        process.reason = self.reason.clone();
+        process
+    }
+}
+
+/// Body of `PATCH /process` and one entry of `PUT /processes`; only the
+/// fields given are changed.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessPatchInput {
+    pub name: String,
+    pub run: Option<bool>,
+    pub tags: Option<Vec<String>>,
+    pub schedule: Option<Schedule>,
+    /// An empty group or list removes the process from its group or its dependencies.
+    pub group: Option<String>,
+    pub depends_on: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    /// Why `run` is being changed; only accepted together with `run`.
+    pub reason: Option<String>,
+}
+
+impl ProcessPatchInput {
+    /// True when at least one field besides the name would be changed.
+    pub fn has_changes(&self) -> bool {
+        self.run.is_some() || self.tags.is_some() || self.schedule.is_some()
+            || self.group.is_some() || self.depends_on.is_some() || !self.metadata.is_empty()
+    }
+
+    /// Trims and deduplicates the tags; called before `validate`.
+    pub fn normalize(&mut self) {
+        normalize_tags(&mut self.tags);
+    }
+
+    /// Every field that breaks a rule, for the process `name` already names.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        self.check(false)
+    }
+
+    /// As `validate`, for an entry of `PUT /processes` adding a process.
+    pub fn validate_new(&self) -> Result<(), Vec<FieldError>> {
+        self.check(true)
+    }
+
+    fn check(&self, new: bool) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, new, &mut errors);
+        check_tags("tags", &self.tags, &mut errors);
+        if self.reason.is_some() && self.run.is_none() {
+            errors.push(FieldError::new("reason", format!("'reason' explains a change to 'run', which is missing for {}.", self.name)));
+        }
+        validate_schedule(&self.schedule, &mut errors);
+        validate_dependencies(&self.name, &self.group, &self.depends_on, &mut errors);
+        self.metadata.validate(&mut errors);
+        check_text(&LONG_TEXT, "reason", &self.reason, &mut errors);
+        validation::into_result(errors)
+    }
+}
+
+/// Body of `POST /process/rename`. The process keeps its run state, tags,
+/// schedule and history under `new_name`. Only `new_name` has to follow the
+/// naming rule, so processes named before it can be renamed into it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessRename {
+    pub name: String,
+    pub new_name: String,
+}
+
+impl ProcessRename {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, false, &mut errors);
+        NAME.check("new_name", &self.new_name, &mut errors);
+        if self.name == self.new_name {
+            errors.push(FieldError::new("new_name", "must differ from name"));
+        }
+        validation::into_result(errors)
+    }
+}
+
+/// Tags to take off and put on, shared by the tag endpoints. `remove` is
+/// applied before `add`; tags already present or absent are left alone.
+fn validate_tag_changes(add: &Option<Vec<String>>, remove: &Option<Vec<String>>, errors: &mut Vec<FieldError>) {
+    if add.iter().chain(remove).all(|tags| tags.is_empty()) {
+        errors.push(FieldError::new("add", "either 'add' or 'remove' must name a tag"));
+    }
+    check_tags("add", add, errors);
+    check_tags("remove", remove, errors);
+    for (i, tag) in remove.iter().flatten().enumerate() {
+        if add.iter().flatten().any(|t| t == tag) {
+            errors.push(FieldError::new(format!("remove[{}]", i), format!("'{}' is also being added", tag)));
+        }
+    }
+}
+
+/// Body of `PATCH /process/tags`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessTagsInput {
+    pub name: String,
+    pub add: Option<Vec<String>>,
+    pub remove: Option<Vec<String>>,
+}
+
+impl ProcessTagsInput {
+    /// Trims and deduplicates the tags; called before `validate`.
+    pub fn normalize(&mut self) {
+        normalize_tags(&mut self.add);
+        normalize_tags(&mut self.remove);
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, false, &mut errors);
+        validate_tag_changes(&self.add, &self.remove, &mut errors);
+        validation::into_result(errors)
+    }
+}
+
+/// Body of `PATCH /processes/tags`, changing the tags of every process
+/// carrying all of `tags` whose name matches any of `name_patterns`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BulkTagsInput {
+    pub name_patterns: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    pub add: Option<Vec<String>>,
+    pub remove: Option<Vec<String>>,
+}
+
+impl BulkTagsInput {
+    /// Trims and deduplicates the tags; called before `validate`.
+    pub fn normalize(&mut self) {
+        normalize_tags(&mut self.add);
+        normalize_tags(&mut self.remove);
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        validation::check_selection(&self.name_patterns, &self.tags, &mut errors);
+        validate_tag_changes(&self.add, &self.remove, &mut errors);
+        validation::into_result(errors)
+    }
+
+    pub fn selection(&self) -> ProcessQuery {
+        ProcessQuery { name_patterns: self.name_patterns.clone(), tags: self.tags.clone(), reason: None }
+    }
+}
+
+/// One tag in use, with how many of the processes carrying it run.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TagSummary {
+    pub tag: String,
+    pub count: usize,
+    pub running: usize,
+    pub stopped: usize,
+}
+
+/// Body of `PATCH /processes/{action}` and `DELETE /processes`, selecting
+/// the processes to start, stop or delete.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessQuery {
+    pub name_patterns: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    /// Recorded as the reason of every process started or stopped.
+    pub reason: Option<String>,
+}
+
+impl ProcessQuery {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        check_text(&LONG_TEXT, "reason", &self.reason, &mut errors);
+        validation::into_result(errors)
+    }
+
+    /// Like `validate`, also requiring a selection, for changes that must
+    /// never reach every process.
+    pub fn validate_selection(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = self.validate().err().unwrap_or_default();
+        validation::check_selection(&self.name_patterns, &self.tags, &mut errors);
+        validation::into_result(errors)
+    }
+}
+
+/// Query string of `GET /processes`. Every field is optional; the
+/// conditions given are combined with AND.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct ProcessQueryParams {
+    /// Processes carrying all of these tags.
+    #[cfg_attr(feature = "openapi", param(rename = "tags[]", style = Form, explode))]
+    pub tags: Option<Vec<String>>,
+    /// Processes carrying at least one of these tags.
+    #[cfg_attr(feature = "openapi", param(rename = "any_tags[]", style = Form, explode))]
+    pub any_tags: Option<Vec<String>>,
+    /// Wildcard (glob) patterns, any of which the name must match.
+    #[cfg_attr(feature = "openapi", param(rename = "name_patterns[]", style = Form, explode, example = json!(["process[1-3]"])))]
+    pub name_patterns: Option<Vec<String>>,
+    pub run: Option<bool>,
+    /// Processes with exactly this owner.
+    pub owner: Option<String>,
+    /// Processes owned by exactly this team.
+    pub team: Option<String>,
+    /// `key=value` labels a process must all carry; a bare key matches any value.
+    #[cfg_attr(feature = "openapi", param(rename = "labels[]", style = Form, explode, example = json!(["env=prod"])))]
+    pub labels: Option<Vec<String>>,
+    /// Terms combined with `AND` (implied between adjacent terms), `OR`,
+    /// `NOT` and parentheses: `tag:dmi`, `name:proc*` (glob) or
+    /// `name~^proc-[0-9]+$` (regex), `run:true`, `owner:`, `team:`,
+    /// `contact:`, `description:`, `reason:` and `group:` (exact, or `~`
+    /// for a regex), `label:env=prod` or `label:env`, and
+    /// `effective>=2024-03-01` (also `>`, `<` and `<=`). Double-quote values
+    /// containing spaces or parentheses.
+    #[cfg_attr(feature = "openapi", param(example = "(tag:dmi OR tag:es) AND NOT name~\"^test-\""))]
+    pub filter: Option<String>,
+    /// Comma separated fields out of `name`, `run` and `effective`, each
+    /// descending when prefixed with `-`. Ties are broken by name.
+    #[cfg_attr(feature = "openapi", param(example = "-effective,name"))]
+    pub sort: Option<String>,
+    /// Most processes to return, between 1 and 1000.
+    #[cfg_attr(feature = "openapi", param(minimum = 1, maximum = 1000))]
+    pub limit: Option<usize>,
+    /// The `X-Next-Cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// Comma separated fields to return; all of them by default.
+    #[cfg_attr(feature = "openapi", param(example = "name,run"))]
+    pub fields: Option<String>,
+}
+
+impl ProcessQueryParams {
+    /// The query string pairs the server parses back into these parameters,
+    /// with lists repeated as `name[]`.
+    pub fn to_query_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        let lists = [("tags", &self.tags), ("any_tags", &self.any_tags), ("name_patterns", &self.name_patterns), ("labels", &self.labels)];
+        for (key, values) in lists {
+            pairs.extend(values.iter().flatten().map(|value| (format!("{}[]", key), value.clone())));
+        }
+        let scalars = [
+            ("run", self.run.map(|r| r.to_string())),
+            ("owner", self.owner.clone()),
+            ("team", self.team.clone()),
+            ("filter", self.filter.clone()),
+            ("sort", self.sort.clone()),
+            ("limit", self.limit.map(|l| l.to_string())),
+            ("cursor", self.cursor.clone()),
+            ("fields", self.fields.clone()),
+        ];
+        pairs.extend(scalars.into_iter().filter_map(|(key, value)| value.map(|v| (key.to_string(), v))));
+        pairs
+    }
+}
+
+/// Sets `slot` from the next value, rejecting a field given twice.
+fn set_once<'de, V, T>(slot: &mut Option<T>, map: &mut V, field: &'static str) -> Result<(), V::Error>
+where
+    V: MapAccess<'de>,
+    T: Deserialize<'de>,
+{
+    if slot.is_some() {
+        return Err(de::Error::duplicate_field(field));
+    }
+    *slot = Some(map.next_value()?);
+    Ok(())
+}
+
+impl<'de> Deserialize<'de> for ProcessQueryParams {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ProcessQueryParamsVisitor;
+
+        impl<'de> Visitor<'de> for ProcessQueryParamsVisitor {
+            type Value = ProcessQueryParams;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct ProcessQueryParams")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<ProcessQueryParams, V::Error>
+            where
+                V: MapAccess<'de>,
+            {
+                let mut params = ProcessQueryParams::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "tags" => set_once(&mut params.tags, &mut map, "tags[]")?,
+                        "any_tags" => set_once(&mut params.any_tags, &mut map, "any_tags[]")?,
+                        "name_patterns" => set_once(&mut params.name_patterns, &mut map, "name_patterns[]")?,
+                        "run" => set_once(&mut params.run, &mut map, "run")?,
+                        "owner" => set_once(&mut params.owner, &mut map, "owner")?,
+                        "team" => set_once(&mut params.team, &mut map, "team")?,
+                        "labels" => set_once(&mut params.labels, &mut map, "labels[]")?,
+                        "filter" => set_once(&mut params.filter, &mut map, "filter")?,
+                        "sort" => set_once(&mut params.sort, &mut map, "sort")?,
+                        "limit" => set_once(&mut params.limit, &mut map, "limit")?,
+                        "cursor" => set_once(&mut params.cursor, &mut map, "cursor")?,
+                        "fields" => set_once(&mut params.fields, &mut map, "fields")?,
+                        _ => return Err(de::Error::unknown_field(&key, FIELDS)),
+                    }
+                }
+                Ok(params)
+            }
+        }
+
+        const FIELDS: &[&str] = &["tags", "any_tags", "name_patterns", "run", "owner", "team", "labels", "filter", "sort", "limit", "cursor", "fields"];
+        deserializer.deserialize_struct("ProcessQueryParams", FIELDS, ProcessQueryParamsVisitor)
+    }
+}
+
+/// What `PUT /processes` did to one process: `Added` or `Updated`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessMessage {
+    pub name: String,
+    pub action: String,
+}
+
+/// The part of a process an audit entry tracks.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessState {
+    pub run: bool,
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl From<&Process> for ProcessState {
+    fn from(p: &Process) -> Self {
+        ProcessState {
+            run: p.run,
+            tags: p.tags.clone(),
+            schedule: p.schedule.clone(),
+            group: p.group.clone(),
+            depends_on: p.depends_on.clone(),
+            metadata: p.metadata.clone(),
+            reason: p.reason.clone(),
+        }
+    }
+}
+
+/// One change to one process. `previous` is empty when the process was
+/// added and `new` is empty when it was deleted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    pub process_name: String,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<ProcessState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<ProcessState>,
+    pub timestamp: String,
+    pub caller: String,
+    pub request_id: String,
+    /// On the `renamed` entry of the new name, the name the process had before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+    /// On the `renamed` entry of the old name, the name the process was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_to: Option<String>,
+}
+
+impl AuditEntry {
+    /// The UTC day the entry belongs to, which names the JSONL file it is stored in.
+    pub fn day(&self) -> &str {
+        self.timestamp.get(..10).unwrap_or(&self.timestamp)
+    }
+}
+
+/// How one process differs between two versions of the processes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProcessChange {
+    pub process_name: String,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<ProcessState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<ProcessState>,
+}
+
+/// One retained version of the process document.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DocumentVersion {
+    /// Identifies the version to `load_version`, e.g. for a rollback.
+    pub version_id: String,
+    /// The version token the document carried, as reported by `/readyz`.
+    pub etag: String,
+    /// When the version was saved, in RFC 3339.
+    pub saved_at: String,
+    /// True for the version currently stored.
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Planned,
+    Applied,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GroupStep {
+    pub processes: Vec<String>,
+    pub status: StepStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What a group change did, step by step.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GroupReport {
+    pub group: String,
+    pub run: bool,
+    /// False when a step failed, leaving the group partly changed.
+    pub complete: bool,
+    pub steps: Vec<GroupStep>,
+}
+
+impl GroupReport {
+    pub fn planned(group: &str, run: bool, steps: Vec<Vec<String>>) -> Self {
+        GroupReport {
+            group: group.to_string(),
+            run,
+            complete: false,
+            steps: steps.into_iter().map(|processes| GroupStep { processes, status: StepStatus::Planned, error: None }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RunState {
+    pub name: String,
+    pub run: bool,
+}
+
+/// Query string of `GET /process/watch`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct WatchParams {
+    /// Wildcard (glob) patterns, any of which the name must match.
+    #[cfg_attr(feature = "openapi", param(rename = "name_patterns[]", style = Form, explode))]
+    pub name_patterns: Option<Vec<String>>,
+    /// Processes carrying all of these tags.
+    #[cfg_attr(feature = "openapi", param(rename = "tags[]", style = Form, explode))]
+    pub tags: Option<Vec<String>>,
+    /// Version token from the previous response; returns as soon as it differs.
+    pub version: Option<String>,
+    /// Seconds to wait for a change before giving up, at most 60.
+    #[cfg_attr(feature = "openapi", param(maximum = 60))]
+    pub timeout: Option<u64>,
+}
+
+impl WatchParams {
+    pub fn to_query_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        for (key, values) in [("tags", &self.tags), ("name_patterns", &self.name_patterns)] {
+            pairs.extend(values.iter().flatten().map(|value| (format!("{}[]", key), value.clone())));
+        }
+        pairs.extend(self.version.clone().map(|v| ("version".to_string(), v)));
+        pairs.extend(self.timeout.map(|t| ("timeout".to_string(), t.to_string())));
+        pairs
+    }
+}
+
+/// The run state of every watched process. `version` only changes when one
+/// of them starts, stops, appears or disappears.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WatchSnapshot {
+    pub version: String,
+    pub processes: Vec<RunState>,
+}
+
+/// Body of every error response. Match on `error`, which is stable, rather
+/// than on `message`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GenericErrorResponse {
+    pub code: u32,
+    pub error: String,
+    pub message: String,
+    /// On a write conflict, the stored state of the processes the request touched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<Vec<Process>>,
+    /// When validation failed, every field that was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldError>>,
+}
+
+#[test]
+fn test_query_pairs_repeat_lists() {
+    let params = ProcessQueryParams {
+        tags: Some(vec!["daily".to_string(), "s3".to_string()]),
+        run: Some(false),
+        limit: Some(10),
+        ..Default::default()
+    };
+    let pairs = params.to_query_pairs();
+    let pairs: Vec<(&str, &str)> = pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    assert_eq!(pairs, vec![("tags[]", "daily"), ("tags[]", "s3"), ("run", "false"), ("limit", "10")]);
+}
+
+#[test]
+fn test_bulk_changes_need_a_selection() {
+    let input = BulkTagsInput { add: Some(vec!["nightly".to_string()]), ..Default::default() };
+    let fields: Vec<String> = input.validate().unwrap_err().into_iter().map(|e| e.field).collect();
+    assert_eq!(fields, vec!["name_patterns"]);
+    let input = BulkTagsInput { tags: Some(Vec::new()), ..input };
+    assert!(input.validate().is_err());
+    let input = BulkTagsInput { name_patterns: Some(vec!["etl-*".to_string()]), ..input };
+    assert!(input.validate().is_ok());
+
+    let query = ProcessQuery { reason: Some("r".repeat(1025)), ..Default::default() };
+    assert!(query.validate().is_err());
+    let fields: Vec<String> = query.validate_selection().unwrap_err().into_iter().map(|e| e.field).collect();
+    assert_eq!(fields, vec!["reason", "name_patterns"]);
+    assert!(ProcessQuery { tags: Some(vec!["qa".to_string()]), ..Default::default() }.validate_selection().is_ok());
+}
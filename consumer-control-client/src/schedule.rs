@@ -0,0 +1,233 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Longest maintenance window accepted, so resolving a window stays cheap.
+const MAX_WINDOW_MINUTES: u32 = 7 * 24 * 60;
+
+/// Planned changes to a process's `run` value that take effect without
+/// anyone calling the API at the time.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Schedule {
+    /// One-off transitions, e.g. stop at 22:00 and start again at 06:00.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transitions: Vec<Transition>,
+    /// Recurring windows during which the process is stopped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Transition {
+    pub at: DateTime<FixedOffset>,
+    pub run: bool,
+}
+
+/// Stops the process for `duration_minutes` from every minute matching
+/// `cron`, a five field cron expression evaluated in UTC.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(from = "WindowFields")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MaintenanceWindow {
+    pub cron: String,
+    pub duration_minutes: u32,
+    // Parsed once, as every read of the process resolves its schedule;
+    // `None` when `cron` is invalid, which `validate` reports.
+    #[serde(skip)]
+    parsed: Option<CronExpr>,
+}
+
+#[derive(Deserialize)]
+struct WindowFields {
+    cron: String,
+    duration_minutes: u32,
+}
+
+impl From<WindowFields> for MaintenanceWindow {
+    fn from(fields: WindowFields) -> Self {
+        MaintenanceWindow::new(&fields.cron, fields.duration_minutes)
+    }
+}
+
+impl MaintenanceWindow {
+    pub fn new(cron: &str, duration_minutes: u32) -> Self {
+        MaintenanceWindow { cron: cron.to_string(), duration_minutes, parsed: CronExpr::parse(cron).ok() }
+    }
+}
+
+impl PartialEq for MaintenanceWindow {
+    fn eq(&self, other: &Self) -> bool {
+        self.cron == other.cron && self.duration_minutes == other.duration_minutes
+    }
+}
+
+impl Schedule {
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty() && self.windows.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for window in &self.windows {
+            CronExpr::parse(&window.cron)?;
+            if window.duration_minutes == 0 || window.duration_minutes > MAX_WINDOW_MINUTES {
+                return Err(format!(
+                    "Maintenance window duration must be between 1 and {} minutes, got {}.",
+                    MAX_WINDOW_MINUTES, window.duration_minutes
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `run` value at `now` for a process that was last explicitly set to
+    /// `run` at `set_at` (formatted like `Process::effective`).
+    ///
+    /// A transition only wins over an explicit change made after it, and an
+    /// open maintenance window wins over both.
+    pub fn resolve(&self, run: bool, set_at: &str, now: DateTime<Utc>) -> bool {
+        let in_window = self.windows.iter().any(|w| {
+            w.parsed.as_ref().is_some_and(|cron| cron.open_at(now, w.duration_minutes))
+        });
+        if in_window {
+            return false;
+        }
+        let set_at = parse_effective(set_at);
+        self.transitions.iter()
+            .filter(|t| t.at <= now && set_at.is_none_or(|set_at| t.at > set_at))
+            .max_by_key(|t| t.at)
+            .map_or(run, |t| t.run)
+    }
+}
+
+/// Accepts both forms `effective` has been stored in.
+fn parse_effective(effective: &str) -> Option<DateTime<Utc>> {
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(effective, format).ok())?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// A parsed `minute hour day-of-month month day-of-week` expression. Each
+/// field accepts `*`, numbers, ranges `a-b`, lists `a,b` and steps `*/n`.
+#[derive(Debug, Clone)]
+struct CronExpr {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpr {
+    fn parse(expr: &str) -> Result<CronExpr, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Invalid cron expression '{}': expected 5 fields.", expr));
+        }
+        let field = |i: usize, min: u32, max: u32| {
+            parse_cron_field(fields[i], min, max)
+                .map_err(|e| format!("Invalid cron expression '{}': {}", expr, e))
+        };
+        let mut weekdays = field(4, 0, 7)?;
+        // Both 0 and 7 mean Sunday.
+        weekdays[0] |= weekdays[7];
+        Ok(CronExpr {
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days: field(2, 1, 31)?,
+            months: field(3, 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn matches(&self, t: DateTime<Utc>) -> bool {
+        let day = self.days[t.day() as usize];
+        let weekday = self.weekdays[t.weekday().num_days_from_sunday() as usize];
+        // As in cron, a restricted day of month and day of week match when either does.
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes[t.minute() as usize] && self.hours[t.hour() as usize] && self.months[t.month() as usize] && day_matches
+    }
+
+    /// True when a window of `duration_minutes` started at some matching minute is still open at `now`.
+    fn open_at(&self, now: DateTime<Utc>, duration_minutes: u32) -> bool {
+        let now = now.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(now);
+        (0..duration_minutes as i64).any(|ago| Duration::try_minutes(ago).is_some_and(|d| self.matches(now - d)))
+    }
+}
+
+/// Returns a lookup table indexed by value, sized `max + 1`.
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| format!("invalid step '{}'", step))?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("invalid step in '{}'", part));
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (parse_cron_value(a, min, max)?, parse_cron_value(b, min, max)?),
+                None => {
+                    let v = parse_cron_value(range, min, max)?;
+                    (v, if part.contains('/') { max } else { v })
+                },
+            },
+        };
+        if start > end {
+            return Err(format!("invalid range '{}'", range));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            allowed[v as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+fn parse_cron_value(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(v) if (min..=max).contains(&v) => Ok(v),
+        _ => Err(format!("'{}' is not between {} and {}", value, min, max)),
+    }
+}
+
+#[test]
+fn test_schedule_resolve() {
+    let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap();
+    let schedule = Schedule {
+        transitions: vec![
+            Transition { at: at("2026-11-03T22:00:00Z"), run: false },
+            Transition { at: at("2026-11-04T06:00:00Z"), run: true },
+        ],
+        windows: vec![MaintenanceWindow::new("0 2 * * 0", 90)],
+    };
+    let utc = |s: &str| at(s).with_timezone(&Utc);
+    assert!(schedule.resolve(true, "", utc("2026-11-03T21:59:00Z")));
+    assert!(!schedule.resolve(true, "", utc("2026-11-03T23:00:00Z")));
+    assert!(schedule.resolve(true, "", utc("2026-11-04T06:00:00Z")));
+    let after_stop = (utc("2026-11-03T23:00:00Z")).with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S").to_string();
+    assert!(schedule.resolve(true, &after_stop, utc("2026-11-03T23:30:00Z")));
+    // 2026-11-08 is a Sunday.
+    assert!(!schedule.resolve(true, "", utc("2026-11-08T03:29:00Z")));
+    assert!(schedule.resolve(true, "", utc("2026-11-08T03:30:00Z")));
+
+    assert!(CronExpr::parse("*/15 8-17 * * 1-5").is_ok());
+    assert!(Schedule { transitions: vec![], windows: vec![MaintenanceWindow::new("61 * * * *", 5)] }
+        .validate().is_err());
+
+    // Deserializing parses the expression, as building the window does.
+    let window: MaintenanceWindow = serde_json::from_str(r#"{"cron":"0 2 * * 0","duration_minutes":90}"#).unwrap();
+    assert!(window.parsed.is_some() && window == schedule.windows[0]);
+    assert_eq!(serde_json::to_value(&window).unwrap(), serde_json::json!({ "cron": "0 2 * * 0", "duration_minutes": 90 }));
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+/// Why one field of a request was rejected. `field` names it the way it
+/// appears in the body, e.g. `tags[2]`, or `[1].name` for an entry of a list.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError { field: field.into(), message: message.into() }
+    }
+}
+
+/// What a name-like string may look like.
+pub struct TextRule {
+    pub max_len: usize,
+    pub allowed: fn(char) -> bool,
+    /// The allowed characters, as told to a caller who used another.
+    pub alphabet: &'static str,
+}
+
+/// Process names, which are matched against glob patterns and end up in
+/// paths, so they are kept clear of wildcards, separators and whitespace.
+/// Group names and the names in `depends_on` follow the same rule.
+pub const NAME: TextRule = TextRule {
+    max_len: 128,
+    allowed: |c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'),
+    alphabet: "letters, digits, '-', '_' and '.'",
+};
+
+pub const TAG: TextRule = TextRule {
+    max_len: 64,
+    allowed: |c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'),
+    alphabet: "letters, digits, '-', '_', '.' and ':'",
+};
+
+/// Most tags one process may carry.
+pub const MAX_TAGS: usize = 32;
+
+/// Owner, team and contact of a process.
+pub const SHORT_TEXT: TextRule = TextRule {
+    max_len: 128,
+    allowed: |c| !c.is_control(),
+    alphabet: "printable characters",
+};
+
+/// Descriptions, and the reasons given for changing `run`, which end up in
+/// every audit entry of the change too.
+pub const LONG_TEXT: TextRule = TextRule {
+    max_len: 1024,
+    allowed: |c| !c.is_control() || c == '\n',
+    alphabet: "printable characters and line breaks",
+};
+
+pub const LABEL_KEY: TextRule = TextRule {
+    max_len: 64,
+    allowed: |c| !c.is_control() && !c.is_whitespace() && !matches!(c, '=' | ','),
+    alphabet: "printable characters other than whitespace, '=' and ','",
+};
+
+pub const LABEL_VALUE: TextRule = TextRule {
+    max_len: 256,
+    allowed: |c| !c.is_control(),
+    alphabet: "printable characters",
+};
+
+/// Most labels one process may carry.
+pub const MAX_LABELS: usize = 32;
+
+impl TextRule {
+    pub fn check(&self, field: &str, value: &str, errors: &mut Vec<FieldError>) {
+        if value.is_empty() {
+            errors.push(FieldError::new(field, "must not be empty"));
+        } else if value.chars().count() > self.max_len {
+            errors.push(FieldError::new(field, format!("must be at most {} characters long", self.max_len)));
+        } else if let Some(c) = value.chars().find(|c| !(self.allowed)(*c)) {
+            errors.push(FieldError::new(field, format!("must only contain {}, not {:?}", self.alphabet, c)));
+        }
+    }
+}
+
+/// Checks free text that may be left out, or sent empty to clear it.
+pub fn check_text(rule: &TextRule, field: &str, value: &Option<String>, errors: &mut Vec<FieldError>) {
+    if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+        rule.check(field, value, errors);
+    }
+}
+
+/// Checks a process name. The name of a process being created follows
+/// `NAME`; one that only addresses an existing process may predate the rule,
+/// so it just must not be empty.
+pub fn check_name(field: &str, name: &str, new: bool, errors: &mut Vec<FieldError>) {
+    if new {
+        NAME.check(field, name, errors);
+    } else if name.is_empty() {
+        errors.push(FieldError::new(field, "must not be empty"));
+    }
+}
+
+/// Trims every tag and drops repeats, keeping the first occurrence.
+pub fn normalize_tags(tags: &mut Option<Vec<String>>) {
+    if let Some(tags) = tags {
+        let mut seen = Vec::with_capacity(tags.len());
+        for tag in tags.drain(..) {
+            let tag = tag.trim().to_string();
+            if !seen.contains(&tag) {
+                seen.push(tag);
+            }
+        }
+        *tags = seen;
+    }
+}
+
+pub fn check_tags(field: &str, tags: &Option<Vec<String>>, errors: &mut Vec<FieldError>) {
+    let Some(tags) = tags else { return };
+    if tags.len() > MAX_TAGS {
+        errors.push(FieldError::new(field, format!("must hold at most {} tags", MAX_TAGS)));
+    }
+    for (i, tag) in tags.iter().enumerate() {
+        TAG.check(&format!("{}[{}]", field, i), tag, errors);
+    }
+}
+
+/// Checks that a bulk change selects processes by name or tag, since an
+/// empty selection would match every process.
+pub fn check_selection(name_patterns: &Option<Vec<String>>, tags: &Option<Vec<String>>, errors: &mut Vec<FieldError>) {
+    if name_patterns.iter().chain(tags).all(|values| values.is_empty()) {
+        errors.push(FieldError::new("name_patterns", "either name_patterns or tags must be given"));
+    }
+}
+
+pub fn into_result(errors: Vec<FieldError>) -> Result<(), Vec<FieldError>> {
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Prefixes every field with the position of the entry it belongs to.
+pub fn at_index(index: usize, errors: Vec<FieldError>) -> Vec<FieldError> {
+    errors.into_iter().map(|e| FieldError::new(format!("[{}].{}", index, e.field), e.message)).collect()
+}
+
+#[test]
+fn test_rules_report_every_field() {
+    let mut tags = Some(vec![" es ".to_string(), "es".to_string(), "md".to_string()]);
+    normalize_tags(&mut tags);
+    assert_eq!(tags, Some(vec!["es".to_string(), "md".to_string()]));
+
+    let mut errors = Vec::new();
+    NAME.check("name", "process*", &mut errors);
+    NAME.check("name", "", &mut errors);
+    NAME.check("name", &"p".repeat(129), &mut errors);
+    NAME.check("name", "etl.load-2_b", &mut errors);
+    check_name("name", "legacy process", false, &mut errors);
+    check_name("name", "", false, &mut errors);
+    check_tags("tags", &Some(vec!["ok".to_string(), "not ok".to_string()]), &mut errors);
+    check_text(&LONG_TEXT, "reason", &Some("r".repeat(1025)), &mut errors);
+    check_text(&LONG_TEXT, "reason", &Some("line\nbreak".to_string()), &mut errors);
+    check_text(&SHORT_TEXT, "owner", &Some(String::new()), &mut errors);
+    let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, vec!["name", "name", "name", "name", "tags[1]", "reason"]);
+    assert_eq!(at_index(3, errors)[4].field, "[3].tags[1]");
+}